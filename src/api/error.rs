@@ -11,12 +11,20 @@ use warp::{Rejection, Reply};
 pub enum ApiError {
     Unauthorized,
     InternalServerError,
+    InvalidBody,
+    InvalidOptions(String),
 
     RoomNotFound(String),
     RoomAlreadyExists(String),
 
     UserNotFound(String),
     UserAlreadyExists(String),
+    UserBanned(String),
+
+    RecordingNotStarted(String),
+    RecordingAlreadyStarted(String),
+
+    EgressNotFound(String),
 }
 
 impl ApiError {
@@ -24,9 +32,16 @@ impl ApiError {
         match self {
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
             ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
-
-            ApiError::RoomNotFound(_) | ApiError::UserNotFound(_) => StatusCode::NOT_FOUND,
-            ApiError::RoomAlreadyExists(_) | ApiError::UserAlreadyExists(_) => StatusCode::CONFLICT,
+            ApiError::InvalidBody | ApiError::InvalidOptions(_) => StatusCode::BAD_REQUEST,
+
+            ApiError::RoomNotFound(_)
+            | ApiError::UserNotFound(_)
+            | ApiError::RecordingNotStarted(_)
+            | ApiError::EgressNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::RoomAlreadyExists(_)
+            | ApiError::UserAlreadyExists(_)
+            | ApiError::RecordingAlreadyStarted(_) => StatusCode::CONFLICT,
+            ApiError::UserBanned(_) => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -38,12 +53,22 @@ impl Display for ApiError {
         match self {
             ApiError::Unauthorized => write!(f, "Invalid management token"),
             ApiError::InternalServerError => write!(f, "Internal Server Error"),
+            ApiError::InvalidBody => write!(f, "Request body is not valid"),
+            ApiError::InvalidOptions(reason) => write!(f, "Invalid options: {}", reason),
 
             ApiError::RoomNotFound(id) => write!(f, "Room with ID {} not found", id),
             ApiError::RoomAlreadyExists(id) => write!(f, "Room with ID {} already exists", id),
 
             ApiError::UserNotFound(id) => write!(f, "User with ID {} not found", id),
             ApiError::UserAlreadyExists(id) => write!(f, "User with ID {} already exists", id),
+            ApiError::UserBanned(id) => write!(f, "User with ID {} is banned from this room", id),
+
+            ApiError::RecordingNotStarted(id) => write!(f, "Room with ID {} is not being recorded", id),
+            ApiError::RecordingAlreadyStarted(id) => {
+                write!(f, "Room with ID {} is already being recorded", id)
+            }
+
+            ApiError::EgressNotFound(id) => write!(f, "Egress with ID {} not found", id),
         }
     }
 }
@@ -1,3 +1,5 @@
+use serde::de::DeserializeOwned;
+use warp::hyper::body::Bytes;
 use warp::{filters::BoxedFilter, reply::Reply};
 use warp::{Filter, Rejection};
 
@@ -24,6 +26,30 @@ fn authorize() -> impl Filter<Extract = ((),), Error = Rejection> + Copy {
     })
 }
 
+/// Parse an optional JSON body, using the default value if the body is empty
+fn optional_json<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Copy
+where
+    T: DeserializeOwned + Default + Send + 'static,
+{
+    warp::body::bytes().and_then(|body: Bytes| async move {
+        if body.is_empty() {
+            return Ok(T::default());
+        }
+
+        serde_json::from_slice(&body).map_err(|_| warp::reject::custom(ApiError::InvalidBody))
+    })
+}
+
+/// Parse a required JSON body
+fn json_body<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Copy
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    warp::body::bytes().and_then(|body: Bytes| async move {
+        serde_json::from_slice(&body).map_err(|_| warp::reject::custom(ApiError::InvalidBody))
+    })
+}
+
 pub fn route() -> BoxedFilter<(impl Reply,)> {
     let room_routes = warp::path("room").and(room::route());
     let user_routes = warp::path("room").and(user::route());
@@ -5,13 +5,15 @@ use std::sync::Arc;
 use warp::{filters::BoxedFilter, http::StatusCode, reply::Reply};
 use warp::{Filter, Rejection};
 
-use crate::api::ApiError;
-use crate::state::room::{Room, ROOMS};
+use crate::api::{json_body, optional_json, ApiError};
+use crate::state::room::{EgressOptions, Room, RoomOptions, ROOMS};
 
 #[derive(Serialize)]
 struct RoomReply {
     #[serde(rename = "videoAllowed")]
     video_allowed: bool,
+    #[serde(rename = "dominantSpeaker")]
+    dominant_speaker: Option<String>,
     users: Vec<()>,
 }
 
@@ -34,9 +36,10 @@ pub fn route() -> BoxedFilter<(impl Reply,)> {
     let get_room = room_filter()
         .and(warp::path::end())
         .and(warp::get())
-        .map(|_room: Arc<Room>| {
+        .map(|room: Arc<Room>| {
             warp::reply::json(&RoomReply {
-                video_allowed: false,
+                video_allowed: room.video_allowed(),
+                dominant_speaker: room.dominant_speaker(),
                 users: Vec::new(),
             })
         });
@@ -44,8 +47,10 @@ pub fn route() -> BoxedFilter<(impl Reply,)> {
     let create_room = warp::path::param::<String>()
         .and(warp::path::end())
         .and(warp::post())
-        .and_then(|id: String| async move {
-            match Room::new(id).await {
+        .and(optional_json())
+        .and_then(|id: String, options: RoomOptions| async move {
+            options.validate().map_err(warp::reject::custom)?;
+            match Room::new(id, options).await {
                 Ok(_) => Ok(warp::reply::with_status(
                     warp::reply::reply(),
                     StatusCode::CREATED,
@@ -65,9 +70,80 @@ pub fn route() -> BoxedFilter<(impl Reply,)> {
             ))
         });
 
+    let start_recording = room_filter()
+        .and(warp::path("recording"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and_then(|room: Arc<Room>| async move {
+            match room.start_recording().await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::CREATED,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    let stop_recording = room_filter()
+        .and(warp::path("recording"))
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and_then(|room: Arc<Room>| async move {
+            match room.stop_recording().await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::NO_CONTENT,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    let get_egresses = room_filter()
+        .and(warp::path("egress"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(|room: Arc<Room>| async move {
+            Ok::<_, Infallible>(warp::reply::json(&room.egresses().await))
+        });
+
+    let start_egress = room_filter()
+        .and(warp::path("egress"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(json_body())
+        .and_then(|room: Arc<Room>, options: EgressOptions| async move {
+            match room.start_egress(options).await {
+                Ok(info) => Ok(warp::reply::with_status(
+                    warp::reply::json(&info),
+                    StatusCode::CREATED,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    let stop_egress = room_filter()
+        .and(warp::path("egress"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and_then(|room: Arc<Room>, id: String| async move {
+            match room.stop_egress(&id).await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::NO_CONTENT,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
     get_rooms
         .or(get_room)
         .or(create_room)
         .or(delete_room)
+        .or(start_recording)
+        .or(stop_recording)
+        .or(get_egresses)
+        .or(start_egress)
+        .or(stop_egress)
         .boxed()
 }
@@ -1,17 +1,32 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::Duration;
 
 use warp::Filter;
 use warp::{filters::BoxedFilter, http::StatusCode, reply::Reply};
 
-use crate::api::ApiError;
+use crate::api::{optional_json, ApiError};
 use crate::state::room::Room;
 
+/// Longest ban that can be given when kicking a user, one year in seconds
+const MAX_BAN_DURATION: u64 = 365 * 24 * 60 * 60;
+
 #[derive(Serialize)]
 struct CreateUserReply {
     token: String,
 }
 
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct KickOptions {
+    /// Shown to the kicked user
+    #[serde(default)]
+    reason: Option<String>,
+    /// Seconds during which the user may not rejoin the room, at most a year
+    #[serde(default)]
+    ban_duration: Option<u64>,
+}
+
 pub fn route() -> BoxedFilter<(impl Reply,)> {
     let root = super::room::room_filter().and(warp::path("user"));
 
@@ -44,5 +59,65 @@ pub fn route() -> BoxedFilter<(impl Reply,)> {
             ))
         });
 
-    create_user.boxed()
+    let kick_user = root
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(optional_json())
+        .and_then(|room: Arc<Room>, id: String, options: KickOptions| async move {
+            if options
+                .ban_duration
+                .map_or(false, |duration| duration > MAX_BAN_DURATION)
+            {
+                return Err(warp::reject::custom(ApiError::InvalidOptions(format!(
+                    "banDuration must be at most {} seconds",
+                    MAX_BAN_DURATION
+                ))));
+            }
+
+            let ban = options.ban_duration.map(Duration::from_secs);
+            match room.kick_user(&id, options.reason, ban).await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::NO_CONTENT,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    let set_muted = root
+        .and(warp::path::param::<String>())
+        .and(warp::path("mute"))
+        .and(warp::path::end())
+        .and(warp::post().map(|| true).or(warp::delete().map(|| false)).unify())
+        .and_then(|room: Arc<Room>, id: String, muted: bool| async move {
+            match room.set_user_muted(&id, muted).await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::NO_CONTENT,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    let set_deafened = root
+        .and(warp::path::param::<String>())
+        .and(warp::path("deafen"))
+        .and(warp::path::end())
+        .and(warp::post().map(|| true).or(warp::delete().map(|| false)).unify())
+        .and_then(|room: Arc<Room>, id: String, deafened: bool| async move {
+            match room.set_user_deafened(&id, deafened).await {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::reply(),
+                    StatusCode::NO_CONTENT,
+                )),
+                Err(err) => Err(warp::reject::custom(err)),
+            }
+        });
+
+    create_user
+        .or(kick_user)
+        .or(set_muted)
+        .or(set_deafened)
+        .boxed()
 }
@@ -11,13 +11,17 @@ pub struct Info {
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Features {
     rtp: bool,
+    /// Whether WebRTC runs on a single port per worker instead of a port range
+    webrtc_server: bool,
 }
 
 pub fn get_info() -> Info {
     let features = Features {
         rtp: !*variables::DISABLE_RTP,
+        webrtc_server: variables::RTC_SERVER_PORT.is_some(),
     };
 
     Info {
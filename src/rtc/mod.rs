@@ -1,45 +1,213 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::num::{NonZeroU32, NonZeroU8};
 
-use crate::state::user::ProduceType;
-use crate::util::variables::{DISABLE_RTP, RTC_IPS};
+use crate::state::{
+    room::{AudioProfile, Room},
+    user::ProduceType,
+};
+use crate::util::variables::{DISABLE_RTP, RTC_IPS, RTC_LISTEN_INFOS, RTC_SRTP_CRYPTO_SUITES};
+use mediasoup::consumer::ConsumerLayers;
 use mediasoup::prelude::*;
+use mediasoup::rtp_parameters::{MediaKind, RtpHeaderExtension, RtpHeaderExtensionUri};
+use mediasoup::sctp_parameters::{NumSctpStreams, SctpStreamParameters};
+use mediasoup::webrtc_server::WebRtcServer;
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
+pub mod stats;
 pub mod types;
 pub mod worker;
 
 pub use worker::get_worker_pool;
 
-pub const SRTP_CRYPTO_SUITE: SrtpCryptoSuite = SrtpCryptoSuite::AesCm128HmacSha180;
-
+use stats::{RtcStats, TransportStats};
 use types::{
-    ConnectTransportData, ConnectTransportParams, InitializationInput, InitializationInputMode,
-    TransportInitData, WebRtcTransportInitData,
+    AutoSubscribe, ConnectTransportData, ConnectTransportParams, InitializationInput,
+    InitializationInputMode, RtpEncryption, RtpTransportOptions, TransportInitData,
+    WebRtcTransportInitData,
 };
 
-pub fn create_opus_codec(channels: u8) -> RtpCodecCapability {
+pub fn create_opus_codec(profile: AudioProfile) -> RtpCodecCapability {
+    let parameters = match profile {
+        AudioProfile::Standard => RtpCodecParametersParameters::default(),
+        AudioProfile::Voice => RtpCodecParametersParameters::from([
+            ("useinbandfec", 1u32.into()),
+            ("usedtx", 1u32.into()),
+            ("stereo", 0u32.into()),
+            ("maxaveragebitrate", 32000u32.into()),
+        ]),
+        AudioProfile::Music => RtpCodecParametersParameters::from([
+            ("useinbandfec", 1u32.into()),
+            ("usedtx", 0u32.into()),
+            ("stereo", 1u32.into()),
+            ("sprop-stereo", 1u32.into()),
+            ("maxaveragebitrate", 128000u32.into()),
+        ]),
+        AudioProfile::LowBandwidth => RtpCodecParametersParameters::from([
+            ("useinbandfec", 1u32.into()),
+            ("usedtx", 1u32.into()),
+            ("stereo", 0u32.into()),
+            ("maxaveragebitrate", 16000u32.into()),
+            ("maxplaybackrate", 16000u32.into()),
+        ]),
+    };
+
     RtpCodecCapability::Audio {
         mime_type: MimeTypeAudio::Opus,
         preferred_payload_type: None,
         clock_rate: NonZeroU32::new(48000).unwrap(),
-        channels: NonZeroU8::new(channels).expect("Invalid number of audio channels provided"),
-        parameters: RtpCodecParametersParameters::default(),
-        rtcp_feedback: Vec::new(),
+        // Opus is always signaled with two channels, stereo is controlled by the parameters
+        channels: NonZeroU8::new(2).unwrap(),
+        parameters,
+        rtcp_feedback: match profile {
+            AudioProfile::Standard => Vec::new(),
+            _ => vec![RtcpFeedback::TransportCc],
+        },
     }
 }
 
+/// Drop the audio header extensions a room's audio profile doesn't use, the standard
+/// profile keeps every extension mediasoup supports
+pub fn filter_header_extensions(extensions: &mut Vec<RtpHeaderExtension>, profile: AudioProfile) {
+    let audio_extensions: &[RtpHeaderExtensionUri] = match profile {
+        AudioProfile::Standard => return,
+        AudioProfile::Voice | AudioProfile::Music => &[
+            RtpHeaderExtensionUri::Mid,
+            RtpHeaderExtensionUri::AudioLevel,
+            RtpHeaderExtensionUri::TransportWideCcDraft01,
+            RtpHeaderExtensionUri::AbsSendTime,
+            RtpHeaderExtensionUri::AbsCaptureTime,
+        ],
+        // Every byte counts, only keep what's needed for speaking detection and congestion control
+        AudioProfile::LowBandwidth => &[
+            RtpHeaderExtensionUri::Mid,
+            RtpHeaderExtensionUri::AudioLevel,
+            RtpHeaderExtensionUri::TransportWideCcDraft01,
+        ],
+    };
+
+    extensions.retain(|extension| {
+        extension.kind != MediaKind::Audio || audio_extensions.contains(&extension.uri)
+    });
+}
+
+/// Listen info for a plain transport exchanging media with `remote`, a plain transport
+/// only listens on one IP so the first configured one of the same address family is used
+pub fn plain_listen_info(remote: IpAddr) -> Option<ListenInfo> {
+    let remote_ipv4 = match remote {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some(),
+    };
+
+    RTC_IPS
+        .iter()
+        .find(|info| info.ip.is_ipv4() == remote_ipv4)
+        .copied()
+}
+
+pub fn create_video_codecs() -> Vec<RtpCodecCapability> {
+    let rtcp_feedback = vec![
+        RtcpFeedback::Nack,
+        RtcpFeedback::NackPli,
+        RtcpFeedback::CcmFir,
+        RtcpFeedback::GoogRemb,
+        RtcpFeedback::TransportCc,
+    ];
+
+    vec![
+        RtpCodecCapability::Video {
+            mime_type: MimeTypeVideo::Vp8,
+            preferred_payload_type: None,
+            clock_rate: NonZeroU32::new(90000).unwrap(),
+            parameters: RtpCodecParametersParameters::default(),
+            rtcp_feedback: rtcp_feedback.clone(),
+        },
+        RtpCodecCapability::Video {
+            mime_type: MimeTypeVideo::Vp9,
+            preferred_payload_type: None,
+            clock_rate: NonZeroU32::new(90000).unwrap(),
+            parameters: RtpCodecParametersParameters::from([("profile-id", 2u32.into())]),
+            rtcp_feedback: rtcp_feedback.clone(),
+        },
+        RtpCodecCapability::Video {
+            mime_type: MimeTypeVideo::H264,
+            preferred_payload_type: None,
+            clock_rate: NonZeroU32::new(90000).unwrap(),
+            parameters: RtpCodecParametersParameters::from([
+                ("packetization-mode", 1u32.into()),
+                ("level-asymmetry-allowed", 1u32.into()),
+                ("profile-level-id", "42e01f".into()),
+            ]),
+            rtcp_feedback,
+        },
+    ]
+}
+
+/// Events of a client's consumers, forwarded from mediasoup's callbacks
+pub enum ConsumerEvent {
+    LayersChanged(ConsumerId, Option<ConsumerLayers>),
+    Closed(ConsumerId, ConsumerCloseReason),
+}
+
+/// Why a request on one of the client's consumers failed
+pub enum ConsumerRequestError {
+    NotFound,
+    /// The consumer exists but mediasoup rejected the request
+    Failed,
+}
+
+/// Why a consumer was closed by the server
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ConsumerCloseReason {
+    /// The consumed user stopped producing or left
+    ProducerClosed,
+}
+
+struct ConsumedProducer {
+    user_id: String,
+    produce_type: ProduceType,
+    /// Set once the client picked the consumer's priority, which is then kept
+    custom_priority: bool,
+}
+
 pub struct RtcState {
+    router: Router,
     rtp_capabilities: RtpCapabilities,
+    auto_subscribe: Option<AutoSubscribe>,
     transport_mode: TransportMode,
     consumers: HashMap<ConsumerId, Consumer>,
+    /// Producers the consumers consume, used to recompute their priorities
+    consumed: HashMap<ConsumerId, ConsumedProducer>,
+    /// Dominant speaker the consumer priorities were last computed for
+    dominant_speaker: Option<String>,
+    data_consumers: HashMap<DataConsumerId, DataConsumer>,
+    /// Whether every consumer is paused by a server deafen
+    deafened: bool,
+    /// Consumers the client wants paused, left paused once the deafen is lifted
+    client_paused: HashSet<ConsumerId>,
+    consumer_sender: UnboundedSender<ConsumerEvent>,
+    consumer_receiver: UnboundedReceiver<ConsumerEvent>,
 }
 
 impl RtcState {
-    pub async fn initialize(router: &Router, init_data: InitializationInput) -> Result<Self, ()> {
-        let mut webrtc_options = WebRtcTransportOptions::new(RTC_IPS.clone());
+    pub async fn initialize(
+        room: &Room,
+        router: &Router,
+        webrtc_server: Option<&WebRtcServer>,
+        init_data: InitializationInput,
+        remote: Option<IpAddr>,
+    ) -> Result<Self, ()> {
+        let mut webrtc_options = match webrtc_server {
+            Some(webrtc_server) => WebRtcTransportOptions::new_with_server(webrtc_server.clone()),
+            None => WebRtcTransportOptions::new(RTC_LISTEN_INFOS.clone()),
+        };
         webrtc_options.enable_udp = true;
         webrtc_options.enable_tcp = true;
         webrtc_options.prefer_udp = true;
+        webrtc_options.enable_sctp = true;
+        webrtc_options.num_sctp_streams = NumSctpStreams::default();
 
         let transport_mode = match init_data.mode {
             InitializationInputMode::SplitWebRtc => {
@@ -59,33 +227,103 @@ impl RtcState {
                 let transport = router.create_webrtc_transport(webrtc_options).await;
                 TransportMode::CombinedWebRtc(transport.map_err(|_| ())?)
             }
-            InitializationInputMode::CombinedRtp => {
+            InitializationInputMode::CombinedRtp(rtp_options) => {
                 // TODO: make it return an error struct instead of ()
                 if *DISABLE_RTP {
                     return Err(());
                 }
 
-                let mut options = PlainTransportOptions::new(RTC_IPS[0]);
-                options.rtcp_mux = true;
-                options.comedia = true;
-                options.enable_srtp = true;
-                options.srtp_crypto_suite = SRTP_CRYPTO_SUITE;
+                // Use the first suite in the server's order of preference the client supports
+                let encryption = RTC_SRTP_CRYPTO_SUITES
+                    .iter()
+                    .find(|encryption| rtp_options.srtp_crypto_suites.contains(*encryption))
+                    .ok_or(())?;
+
+                // The client's address is the best guess of where media is exchanged with
+                let listen_info = remote
+                    .and_then(plain_listen_info)
+                    .or_else(|| RTC_IPS.first().copied())
+                    .ok_or(())?;
+                let mut options = PlainTransportOptions::new(listen_info);
+                options.rtcp_mux = rtp_options.rtcp_mux;
+                options.comedia = rtp_options.comedia;
+                if let Some(crypto_suite) = encryption.crypto_suite() {
+                    options.enable_srtp = true;
+                    options.srtp_crypto_suite = crypto_suite;
+                }
                 let transport = router.create_plain_transport(options).await;
-                TransportMode::CombinedRtp(transport.map_err(|_| ())?)
+                TransportMode::CombinedRtp(transport.map_err(|_| ())?, rtp_options)
             }
         };
 
+        // Incoming media arrives on the send transport, outgoing media leaves on the receive transport
+        if let Some(bitrate) = room.max_incoming_bitrate() {
+            match &transport_mode {
+                TransportMode::SplitWebRtc(send, _) => send.set_max_incoming_bitrate(bitrate).await,
+                TransportMode::CombinedWebRtc(transport) => {
+                    transport.set_max_incoming_bitrate(bitrate).await
+                }
+                TransportMode::CombinedRtp(transport, _) => {
+                    transport.set_max_incoming_bitrate(bitrate).await
+                }
+            }
+            .map_err(|_| ())?;
+        }
+        // mediasoup can only limit what is sent over WebRTC, RTP clients aren't capped
+        if let Some(bitrate) = room.max_outgoing_bitrate() {
+            let recv = match &transport_mode {
+                TransportMode::SplitWebRtc(_, recv) => Some(recv),
+                TransportMode::CombinedWebRtc(transport) => Some(transport),
+                TransportMode::CombinedRtp(..) => None,
+            };
+            if let Some(recv) = recv {
+                recv.set_max_outgoing_bitrate(bitrate)
+                    .await
+                    .map_err(|_| ())?;
+            }
+        }
+
+        let (consumer_sender, consumer_receiver) = mpsc::unbounded_channel();
         Ok(RtcState {
+            router: router.clone(),
             rtp_capabilities: init_data.rtp_capabilities,
+            auto_subscribe: init_data.auto_subscribe,
             transport_mode,
             consumers: HashMap::new(),
+            consumed: HashMap::new(),
+            dominant_speaker: room.dominant_speaker(),
+            data_consumers: HashMap::new(),
+            deafened: false,
+            client_paused: HashSet::new(),
+            consumer_sender,
+            consumer_receiver,
         })
     }
 
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
     pub fn rtp_capabilities(&self) -> &RtpCapabilities {
         &self.rtp_capabilities
     }
 
+    pub fn auto_subscribe(&self) -> Option<AutoSubscribe> {
+        self.auto_subscribe
+    }
+
+    /// Whether this client already consumes a producer
+    pub fn consuming(&self, producer_id: ProducerId) -> bool {
+        self.consumers
+            .values()
+            .any(|consumer| consumer.producer_id() == producer_id)
+    }
+
+    /// Whether this client can consume a producer living on `router`
+    pub fn can_consume(&self, router: &Router, producer: &Producer) -> bool {
+        router.can_consume(&producer.id(), &self.rtp_capabilities)
+    }
+
     pub fn get_init_data(&self) -> TransportInitData {
         match &self.transport_mode {
             TransportMode::SplitWebRtc(send, recv) => TransportInitData::SplitWebRtc {
@@ -95,16 +333,25 @@ impl RtcState {
             TransportMode::CombinedWebRtc(transport) => TransportInitData::CombinedWebRtc {
                 transport: RtcState::get_webrtc_init_data(&transport),
             },
-            TransportMode::CombinedRtp(transport) => {
+            TransportMode::CombinedRtp(transport, _) => {
                 let tuple = transport.tuple();
-                let ip = RTC_IPS[0];
-                let ip = ip.announced_ip.unwrap_or(ip.ip);
+                let local_ip = tuple.local_ip();
+                let ip = RTC_IPS
+                    .iter()
+                    .find(|info| info.ip == local_ip || info.announced_ip == Some(local_ip))
+                    .and_then(|info| info.announced_ip)
+                    .unwrap_or(local_ip);
                 TransportInitData::CombinedRtp {
                     ip,
                     port: tuple.local_port(),
+                    rtcp_port: transport.rtcp_tuple().map(|tuple| tuple.local_port()),
                     protocol: tuple.protocol(),
                     id: transport.id(),
-                    srtp_crypto_suite: SRTP_CRYPTO_SUITE,
+                    srtp_crypto_suite: transport
+                        .srtp_parameters()
+                        .map_or(RtpEncryption::None, |parameters| {
+                            parameters.crypto_suite.into()
+                        }),
                 }
             }
         }
@@ -138,7 +385,9 @@ impl RtcState {
 
     pub fn get_rtp_transport_by_id(&self, id: TransportId) -> Option<&PlainTransport> {
         match self.transport_mode {
-            TransportMode::CombinedRtp(ref transport) => Some(transport).filter(|t| t.id() == id),
+            TransportMode::CombinedRtp(ref transport, _) => {
+                Some(transport).filter(|t| t.id() == id)
+            }
             _ => None,
         }
     }
@@ -163,17 +412,28 @@ impl RtcState {
                     Err(())
                 }
             }
-            TransportMode::CombinedRtp(..) => {
-                if let ConnectTransportParams::Rtp { srtp_parameters } = &connect_data.params {
+            TransportMode::CombinedRtp(_, ref options) => {
+                if let ConnectTransportParams::Rtp {
+                    srtp_parameters,
+                    ip,
+                    port,
+                    rtcp_port,
+                } = &connect_data.params
+                {
                     let transport = self
                         .get_rtp_transport_by_id(connect_data.id)
                         .ok_or_else(|| ())?;
+                    let encrypted = transport.srtp_parameters().is_some();
+                    if !connect_data.params.valid_for_rtp(options, encrypted) {
+                        return Err(());
+                    }
+
                     transport
                         .connect(PlainTransportRemoteParameters {
-                            ip: None,
-                            port: None,
-                            rtcp_port: None,
-                            srtp_parameters: Some(srtp_parameters.clone()),
+                            ip: *ip,
+                            port: *port,
+                            rtcp_port: *rtcp_port,
+                            srtp_parameters: srtp_parameters.clone(),
                         })
                         .await
                         .map_err(|_| ())?;
@@ -186,34 +446,147 @@ impl RtcState {
         }
     }
 
+    /// Restart ICE on a WebRTC transport, returning the new ICE parameters
+    pub async fn restart_ice(&self, id: TransportId) -> Result<IceParameters, ()> {
+        let transport = self.get_webrtc_transport_by_id(id).ok_or_else(|| ())?;
+        transport.restart_ice().await.map_err(|_| ())
+    }
+
     pub async fn start_produce(
         &self,
         produce_type: &ProduceType,
         rtp_parameters: RtpParameters,
+        paused: bool,
     ) -> Result<Producer, ProduceError> {
         let transport = self.transport_mode.send();
-        transport
-            .produce(ProducerOptions::new(
-                produce_type.into_kind(),
-                rtp_parameters,
-            ))
-            .await
+        let mut options = ProducerOptions::new(produce_type.into_kind(), rtp_parameters);
+        options.paused = paused;
+        transport.produce(options).await
     }
 
+    /// Consume a user's producer living on `source`, piping it to this state's router first if needed
     pub async fn start_consume(
         &mut self,
+        room: &Room,
+        source: &Router,
+        producing_id: &str,
+        produce_type: ProduceType,
         producer_id: ProducerId,
+        paused: bool,
     ) -> Result<Consumer, ConsumeError> {
+        if source.id() != self.router.id() {
+            room.pipe_producer(producer_id, source, &self.router)
+                .await
+                .map_err(|_| ConsumeError::ProducerNotFound(producer_id))?;
+        }
+
         let transport = self.transport_mode.recv();
         let mut options = ConsumerOptions::new(producer_id, self.rtp_capabilities.clone());
-        options.paused = true;
+        options.paused = paused || self.deafened;
         let consumer = transport.consume(options).await?;
+        if paused {
+            self.client_paused.insert(consumer.id());
+        }
+
+        let priority = room.consumer_priority(producing_id, produce_type);
+        if consumer.set_priority(priority.get()).await.is_err() {
+            warn!("Failed to set priority of consumer {}", consumer.id());
+        }
+
+        let sender = self.consumer_sender.clone();
+        let consumer_id = consumer.id();
+        consumer
+            .on_layers_change(move |layers| {
+                sender
+                    .send(ConsumerEvent::LayersChanged(consumer_id, *layers))
+                    .ok();
+            })
+            .detach();
+
+        let sender = self.consumer_sender.clone();
+        consumer
+            .on_producer_close(move || {
+                sender
+                    .send(ConsumerEvent::Closed(
+                        consumer_id,
+                        ConsumerCloseReason::ProducerClosed,
+                    ))
+                    .ok();
+            })
+            .detach();
+
         self.consumers.insert(consumer.id(), consumer.clone());
+        self.consumed.insert(
+            consumer.id(),
+            ConsumedProducer {
+                user_id: producing_id.to_string(),
+                produce_type,
+                custom_priority: false,
+            },
+        );
         Ok(consumer)
     }
 
+    /// Recompute the priority of the video consumers of the previous and the new
+    /// dominant speaker, used once the dominant speaker changed
+    pub async fn refresh_priorities(&mut self, room: &Room) {
+        let dominant_speaker = room.dominant_speaker();
+        if dominant_speaker == self.dominant_speaker {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.dominant_speaker, dominant_speaker);
+
+        for (id, consumed) in &self.consumed {
+            let speaker = Some(&consumed.user_id);
+            if consumed.custom_priority
+                || consumed.produce_type != ProduceType::Video
+                || (speaker != previous.as_ref() && speaker != self.dominant_speaker.as_ref())
+            {
+                continue;
+            }
+
+            if let Some(consumer) = self.consumers.get(id) {
+                let priority = room.consumer_priority(&consumed.user_id, consumed.produce_type);
+                if consumer.set_priority(priority.get()).await.is_err() {
+                    warn!("Failed to set priority of consumer {}", id);
+                }
+            }
+        }
+    }
+
+    /// Wait for the next event of one of this client's consumers
+    pub async fn consumer_event(&mut self) -> Option<ConsumerEvent> {
+        self.consumer_receiver.recv().await
+    }
+
+    pub fn deafened(&self) -> bool {
+        self.deafened
+    }
+
+    /// Pause every consumer until undeafened, then resume the ones the client didn't pause itself
+    pub async fn set_deafened(&mut self, deafened: bool) {
+        if self.deafened == deafened {
+            return;
+        }
+
+        self.deafened = deafened;
+        for (id, consumer) in &self.consumers {
+            let result = match deafened {
+                true => consumer.pause().await,
+                false if !self.client_paused.contains(id) => consumer.resume().await,
+                false => continue,
+            };
+
+            if result.is_err() {
+                warn!("Failed to update deafen of consumer {}", id);
+            }
+        }
+    }
+
     pub fn stop_consume(&mut self, consumer_id: &ConsumerId) -> Result<(), ()> {
         let _ = self.consumers.remove(consumer_id).ok_or_else(|| ())?;
+        self.consumed.remove(consumer_id);
+        self.client_paused.remove(consumer_id);
         Ok(())
     }
 
@@ -224,16 +597,156 @@ impl RtcState {
     ) -> Result<(), ()> {
         let consumer = self.consumers.get_mut(consumer_id).ok_or_else(|| ())?;
         match paused {
-            true => consumer.pause().await.map_err(|_| ()),
-            false => consumer.resume().await.map_err(|_| ()),
+            true => consumer.pause().await.map_err(|_| ())?,
+            false => consumer.resume().await.map_err(|_| ())?,
+        }
+
+        match paused {
+            true => self.client_paused.insert(*consumer_id),
+            false => self.client_paused.remove(consumer_id),
+        };
+        Ok(())
+    }
+
+    pub async fn set_consumer_priority(
+        &mut self,
+        consumer_id: &ConsumerId,
+        priority: NonZeroU8,
+    ) -> Result<(), ()> {
+        let consumer = self.consumers.get(consumer_id).ok_or_else(|| ())?;
+        consumer
+            .set_priority(priority.get())
+            .await
+            .map_err(|_| ())?;
+        if let Some(consumed) = self.consumed.get_mut(consumer_id) {
+            consumed.custom_priority = true;
         }
+        Ok(())
+    }
+
+    pub async fn request_key_frame(
+        &self,
+        consumer_id: &ConsumerId,
+    ) -> Result<(), ConsumerRequestError> {
+        let consumer = self
+            .consumers
+            .get(consumer_id)
+            .ok_or(ConsumerRequestError::NotFound)?;
+        consumer.request_key_frame().await.map_err(|err| {
+            warn!(
+                "Failed to request key frame on consumer {}: {}",
+                consumer_id, err
+            );
+            ConsumerRequestError::Failed
+        })
+    }
+
+    /// Pick the simulcast or SVC layers forwarded to the client, the highest available ones are used up to these
+    pub async fn set_preferred_layers(
+        &self,
+        consumer_id: &ConsumerId,
+        layers: ConsumerLayers,
+    ) -> Result<(), ConsumerRequestError> {
+        let consumer = self
+            .consumers
+            .get(consumer_id)
+            .ok_or(ConsumerRequestError::NotFound)?;
+        consumer.set_preferred_layers(layers).await.map_err(|err| {
+            warn!(
+                "Failed to set preferred layers on consumer {}: {}",
+                consumer_id, err
+            );
+            ConsumerRequestError::Failed
+        })
+    }
+
+    pub async fn start_data_produce(
+        &self,
+        sctp_stream_parameters: SctpStreamParameters,
+        label: String,
+        protocol: String,
+    ) -> Result<DataProducer, ProduceDataError> {
+        let transport = self.transport_mode.send();
+        let mut options = DataProducerOptions::new_sctp(sctp_stream_parameters);
+        options.label = label;
+        options.protocol = protocol;
+        transport.produce_data(options).await
+    }
+
+    /// Consume a data producer living on `source`, piping it to this state's router first if needed
+    pub async fn start_data_consume(
+        &mut self,
+        room: &Room,
+        source: &Router,
+        data_producer_id: DataProducerId,
+    ) -> Result<DataConsumer, ConsumeDataError> {
+        if source.id() != self.router.id() {
+            room.pipe_data_producer(data_producer_id, source, &self.router)
+                .await
+                .map_err(|_| ConsumeDataError::DataProducerNotFound(data_producer_id))?;
+        }
+
+        let transport = self.transport_mode.recv();
+        let data_consumer = transport
+            .consume_data(DataConsumerOptions::new_sctp(data_producer_id))
+            .await?;
+        self.data_consumers
+            .insert(data_consumer.id(), data_consumer.clone());
+        Ok(data_consumer)
+    }
+
+    pub fn stop_data_consume(&mut self, data_consumer_id: &DataConsumerId) -> Result<(), ()> {
+        let _ = self
+            .data_consumers
+            .remove(data_consumer_id)
+            .ok_or_else(|| ())?;
+        Ok(())
+    }
+
+    /// Collect stats for the transports, the given producers and every consumer of this client
+    pub async fn get_stats(&self, producers: Vec<(ProduceType, Producer)>) -> Result<RtcStats, ()> {
+        let mut transports: Vec<TransportStats> = Vec::new();
+        match &self.transport_mode {
+            TransportMode::SplitWebRtc(send, recv) => {
+                for transport in [send, recv] {
+                    let stats = transport.get_stats().await.map_err(|_| ())?;
+                    transports.extend(stats.into_iter().map(TransportStats::from));
+                }
+            }
+            TransportMode::CombinedWebRtc(transport) => {
+                let stats = transport.get_stats().await.map_err(|_| ())?;
+                transports.extend(stats.into_iter().map(TransportStats::from));
+            }
+            TransportMode::CombinedRtp(transport, _) => {
+                let stats = transport.get_stats().await.map_err(|_| ())?;
+                transports.extend(stats.into_iter().map(TransportStats::from));
+            }
+        }
+
+        let mut producer_stats = HashMap::new();
+        for (produce_type, producer) in producers {
+            let stats = producer.get_stats().await.map_err(|_| ())?;
+            producer_stats.insert(produce_type, stats.into_iter().map(Into::into).collect());
+        }
+
+        let mut consumer_stats = HashMap::new();
+        for (id, consumer) in &self.consumers {
+            let stats = consumer.get_stats().await.map_err(|_| ())?;
+            consumer_stats.insert(*id, stats.into());
+        }
+
+        Ok(RtcStats {
+            transports,
+            producers: producer_stats,
+            consumers: consumer_stats,
+        })
     }
 }
 
 enum TransportMode {
     SplitWebRtc(WebRtcTransport, WebRtcTransport),
     CombinedWebRtc(WebRtcTransport),
-    CombinedRtp(PlainTransport),
+    CombinedRtp(PlainTransport, RtpTransportOptions),
 }
 
 impl TransportMode {
@@ -249,7 +762,7 @@ impl TransportMode {
         match self {
             TransportMode::SplitWebRtc(ref send, _) => send,
             TransportMode::CombinedWebRtc(ref transport) => transport,
-            TransportMode::CombinedRtp(ref transport) => transport,
+            TransportMode::CombinedRtp(ref transport, _) => transport,
         }
     }
 
@@ -257,7 +770,7 @@ impl TransportMode {
         match self {
             TransportMode::SplitWebRtc(_, ref recv) => recv,
             TransportMode::CombinedWebRtc(ref transport) => transport,
-            TransportMode::CombinedRtp(ref transport) => transport,
+            TransportMode::CombinedRtp(ref transport, _) => transport,
         }
     }
 }
@@ -0,0 +1,138 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use mediasoup::consumer::{ConsumerStat, ConsumerStats};
+use mediasoup::data_structures::{DtlsState, IceState};
+use mediasoup::plain_transport::PlainTransportStat;
+use mediasoup::prelude::*;
+use mediasoup::producer::ProducerStat;
+use mediasoup::rtp_parameters::MimeType;
+use mediasoup::webrtc_transport::WebRtcTransportStat;
+
+use crate::state::user::ProduceType;
+
+/// Connection statistics of a client, as sent over WebSocket
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtcStats {
+    pub transports: Vec<TransportStats>,
+    pub producers: HashMap<ProduceType, Vec<RtpStreamStats>>,
+    pub consumers: HashMap<ConsumerId, ConsumerStreamStats>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportStats {
+    pub id: TransportId,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub recv_bitrate: u32,
+    pub send_bitrate: u32,
+    pub rtp_packet_loss_received: Option<f64>,
+    pub rtp_packet_loss_sent: Option<f64>,
+    /// Only set for WebRTC transports
+    pub ice_state: Option<IceState>,
+    /// Only set for WebRTC transports
+    pub dtls_state: Option<DtlsState>,
+}
+
+impl From<WebRtcTransportStat> for TransportStats {
+    fn from(stat: WebRtcTransportStat) -> Self {
+        TransportStats {
+            id: stat.transport_id,
+            bytes_received: stat.bytes_received,
+            bytes_sent: stat.bytes_sent,
+            recv_bitrate: stat.recv_bitrate,
+            send_bitrate: stat.send_bitrate,
+            rtp_packet_loss_received: stat.rtp_packet_loss_received,
+            rtp_packet_loss_sent: stat.rtp_packet_loss_sent,
+            ice_state: Some(stat.ice_state),
+            dtls_state: Some(stat.dtls_state),
+        }
+    }
+}
+
+impl From<PlainTransportStat> for TransportStats {
+    fn from(stat: PlainTransportStat) -> Self {
+        TransportStats {
+            id: stat.transport_id,
+            bytes_received: stat.bytes_received,
+            bytes_sent: stat.bytes_sent,
+            recv_bitrate: stat.recv_bitrate,
+            send_bitrate: stat.send_bitrate,
+            rtp_packet_loss_received: stat.rtp_packet_loss_received,
+            rtp_packet_loss_sent: stat.rtp_packet_loss_sent,
+            ice_state: None,
+            dtls_state: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtpStreamStats {
+    pub ssrc: u32,
+    pub mime_type: MimeType,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub bitrate: u32,
+    pub packets_lost: u64,
+    pub fraction_lost: u8,
+    pub score: u8,
+    pub round_trip_time: Option<f32>,
+}
+
+impl From<ProducerStat> for RtpStreamStats {
+    fn from(stat: ProducerStat) -> Self {
+        RtpStreamStats {
+            ssrc: stat.ssrc,
+            mime_type: stat.mime_type,
+            packet_count: stat.packet_count,
+            byte_count: stat.byte_count,
+            bitrate: stat.bitrate,
+            packets_lost: stat.packets_lost,
+            fraction_lost: stat.fraction_lost,
+            score: stat.score,
+            round_trip_time: stat.round_trip_time,
+        }
+    }
+}
+
+impl From<ConsumerStat> for RtpStreamStats {
+    fn from(stat: ConsumerStat) -> Self {
+        RtpStreamStats {
+            ssrc: stat.ssrc,
+            mime_type: stat.mime_type,
+            packet_count: stat.packet_count,
+            byte_count: stat.byte_count,
+            bitrate: stat.bitrate,
+            packets_lost: stat.packets_lost,
+            fraction_lost: stat.fraction_lost,
+            score: stat.score,
+            round_trip_time: stat.round_trip_time,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerStreamStats {
+    pub consumer: RtpStreamStats,
+    /// Stream of the consumed producer, only reported by mediasoup in some cases
+    pub producer: Option<RtpStreamStats>,
+}
+
+impl From<ConsumerStats> for ConsumerStreamStats {
+    fn from(stats: ConsumerStats) -> Self {
+        match stats {
+            ConsumerStats::JustConsumer((consumer,)) => ConsumerStreamStats {
+                consumer: consumer.into(),
+                producer: None,
+            },
+            ConsumerStats::WithProducer((consumer, producer)) => ConsumerStreamStats {
+                consumer: consumer.into(),
+                producer: Some(producer.into()),
+            },
+        }
+    }
+}
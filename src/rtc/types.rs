@@ -1,19 +1,33 @@
+use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use std::str::FromStr;
 
 use mediasoup::prelude::*;
 use mediasoup::sctp_parameters::SctpParameters;
 use mediasoup::srtp_parameters::SrtpParameters;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InitializationInput {
     pub(super) rtp_capabilities: RtpCapabilities,
+    /// Have the server consume every producer in the room for the client
+    #[serde(default)]
+    pub(super) auto_subscribe: Option<AutoSubscribe>,
     #[serde(flatten)]
     pub(super) mode: InitializationInputMode,
 }
 
-#[derive(Serialize, Deserialize)]
+/// State consumers created by auto-subscribe start in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoSubscribe {
+    /// The client resumes them with SetConsumerPause once it is ready
+    Paused,
+    Live,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "mode")]
 pub enum InitializationInputMode {
     #[serde(rename = "SplitWebRTC")]
@@ -21,7 +35,77 @@ pub enum InitializationInputMode {
     #[serde(rename = "CombinedWebRTC")]
     CombinedWebRtc,
     #[serde(rename = "CombinedRTP")]
-    CombinedRtp,
+    CombinedRtp(RtpTransportOptions),
+}
+
+/// Options of a plain RTP transport, chosen by the client
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RtpTransportOptions {
+    /// Learn the remote address from the first packet received,
+    /// otherwise it has to be passed when connecting the transport
+    #[serde(default = "default_true")]
+    pub comedia: bool,
+    /// Send RTCP over the RTP port instead of a separate port
+    #[serde(default = "default_true")]
+    pub rtcp_mux: bool,
+    /// Encryption the client supports, the server picks the one it prefers
+    #[serde(default = "default_srtp_crypto_suites")]
+    pub srtp_crypto_suites: Vec<RtpEncryption>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_srtp_crypto_suites() -> Vec<RtpEncryption> {
+    vec![RtpEncryption::AesCm128HmacSha180]
+}
+
+/// SRTP crypto suite used by a plain RTP transport, or none for unencrypted RTP
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtpEncryption {
+    #[serde(rename = "AEAD_AES_256_GCM")]
+    AeadAes256Gcm,
+    #[serde(rename = "AEAD_AES_128_GCM")]
+    AeadAes128Gcm,
+    #[serde(rename = "AES_CM_128_HMAC_SHA1_80")]
+    AesCm128HmacSha180,
+    #[serde(rename = "AES_CM_128_HMAC_SHA1_32")]
+    AesCm128HmacSha132,
+    #[serde(rename = "none")]
+    None,
+}
+
+impl RtpEncryption {
+    pub fn crypto_suite(self) -> Option<SrtpCryptoSuite> {
+        match self {
+            RtpEncryption::AeadAes256Gcm => Some(SrtpCryptoSuite::AeadAes256Gcm),
+            RtpEncryption::AeadAes128Gcm => Some(SrtpCryptoSuite::AeadAes128Gcm),
+            RtpEncryption::AesCm128HmacSha180 => Some(SrtpCryptoSuite::AesCm128HmacSha180),
+            RtpEncryption::AesCm128HmacSha132 => Some(SrtpCryptoSuite::AesCm128HmacSha132),
+            RtpEncryption::None => None,
+        }
+    }
+}
+
+impl From<SrtpCryptoSuite> for RtpEncryption {
+    fn from(crypto_suite: SrtpCryptoSuite) -> RtpEncryption {
+        match crypto_suite {
+            SrtpCryptoSuite::AeadAes256Gcm => RtpEncryption::AeadAes256Gcm,
+            SrtpCryptoSuite::AeadAes128Gcm => RtpEncryption::AeadAes128Gcm,
+            SrtpCryptoSuite::AesCm128HmacSha180 => RtpEncryption::AesCm128HmacSha180,
+            SrtpCryptoSuite::AesCm128HmacSha132 => RtpEncryption::AesCm128HmacSha132,
+        }
+    }
+}
+
+/// Parses the same names as the serde representation
+impl FromStr for RtpEncryption {
+    type Err = serde::de::value::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
 }
 
 #[derive(Serialize)]
@@ -40,9 +124,11 @@ pub enum TransportInitData {
     CombinedRtp {
         ip: IpAddr,
         port: u16,
+        /// Only set if RTCP is not multiplexed with RTP
+        rtcp_port: Option<u16>,
         protocol: Protocol,
         id: TransportId,
-        srtp_crypto_suite: SrtpCryptoSuite,
+        srtp_crypto_suite: RtpEncryption,
     },
 }
 
@@ -70,5 +156,151 @@ pub enum ConnectTransportParams {
     #[serde(rename_all = "camelCase")]
     WebRtc { dtls_parameters: DtlsParameters },
     #[serde(rename_all = "camelCase")]
-    Rtp { srtp_parameters: SrtpParameters },
+    Rtp {
+        /// Required unless the transport is unencrypted
+        srtp_parameters: Option<SrtpParameters>,
+        /// Remote address, only used if the transport was created without comedia
+        ip: Option<IpAddr>,
+        port: Option<u16>,
+        /// Only used if RTCP is not multiplexed with RTP
+        rtcp_port: Option<u16>,
+    },
+}
+
+impl ConnectTransportParams {
+    /// Whether the parameters can connect a plain RTP transport created with `options`,
+    /// `encrypted` tells if the transport uses SRTP
+    pub(super) fn valid_for_rtp(&self, options: &RtpTransportOptions, encrypted: bool) -> bool {
+        match self {
+            ConnectTransportParams::Rtp {
+                srtp_parameters,
+                ip,
+                port,
+                rtcp_port,
+            } => {
+                // Comedia transports learn the remote address, the others need all of it
+                let address_valid = match options.comedia {
+                    true => ip.is_none() && port.is_none() && rtcp_port.is_none(),
+                    false => {
+                        ip.is_some() && port.is_some() && rtcp_port.is_none() == options.rtcp_mux
+                    }
+                };
+
+                // SRTP parameters are only needed if the transport is encrypted
+                address_valid && srtp_parameters.is_some() == encrypted
+            }
+            ConnectTransportParams::WebRtc { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use mediasoup::data_structures::DtlsRole;
+
+    use super::*;
+
+    fn options(comedia: bool, rtcp_mux: bool) -> RtpTransportOptions {
+        RtpTransportOptions {
+            comedia,
+            rtcp_mux,
+            srtp_crypto_suites: default_srtp_crypto_suites(),
+        }
+    }
+
+    fn params(
+        encrypted: bool,
+        port: Option<u16>,
+        rtcp_port: Option<u16>,
+    ) -> ConnectTransportParams {
+        ConnectTransportParams::Rtp {
+            srtp_parameters: encrypted.then(|| SrtpParameters {
+                crypto_suite: SrtpCryptoSuite::AesCm128HmacSha180,
+                key_base64: "a2V5".to_string(),
+            }),
+            ip: port.map(|_| IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            port,
+            rtcp_port,
+        }
+    }
+
+    const ENCRYPTIONS: [(RtpEncryption, &str); 5] = [
+        (RtpEncryption::AeadAes256Gcm, "AEAD_AES_256_GCM"),
+        (RtpEncryption::AeadAes128Gcm, "AEAD_AES_128_GCM"),
+        (RtpEncryption::AesCm128HmacSha180, "AES_CM_128_HMAC_SHA1_80"),
+        (RtpEncryption::AesCm128HmacSha132, "AES_CM_128_HMAC_SHA1_32"),
+        (RtpEncryption::None, "none"),
+    ];
+
+    #[test]
+    fn rtp_encryption_parses_serde_names() {
+        for (encryption, name) in ENCRYPTIONS {
+            assert_eq!(name.parse::<RtpEncryption>().unwrap(), encryption);
+            assert_eq!(serde_json::to_value(encryption).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn rtp_encryption_rejects_unknown_names() {
+        assert!("".parse::<RtpEncryption>().is_err());
+        assert!("aead_aes_256_gcm".parse::<RtpEncryption>().is_err());
+        assert!("AeadAes256Gcm".parse::<RtpEncryption>().is_err());
+    }
+
+    #[test]
+    fn rtp_encryption_crypto_suite_round_trip() {
+        for (encryption, _) in ENCRYPTIONS {
+            match encryption.crypto_suite() {
+                Some(crypto_suite) => assert_eq!(RtpEncryption::from(crypto_suite), encryption),
+                None => assert_eq!(encryption, RtpEncryption::None),
+            }
+        }
+    }
+
+    #[test]
+    fn comedia_rejects_remote_address() {
+        let options = options(true, true);
+
+        assert!(params(false, None, None).valid_for_rtp(&options, false));
+        assert!(!params(false, Some(5000), None).valid_for_rtp(&options, false));
+        assert!(!params(false, None, Some(5001)).valid_for_rtp(&options, false));
+    }
+
+    #[test]
+    fn without_comedia_requires_remote_address() {
+        let options = options(false, true);
+
+        assert!(params(false, Some(5000), None).valid_for_rtp(&options, false));
+        assert!(!params(false, None, None).valid_for_rtp(&options, false));
+    }
+
+    #[test]
+    fn rtcp_port_only_without_rtcp_mux() {
+        assert!(params(false, Some(5000), Some(5001)).valid_for_rtp(&options(false, false), false));
+        assert!(!params(false, Some(5000), None).valid_for_rtp(&options(false, false), false));
+        assert!(!params(false, Some(5000), Some(5001)).valid_for_rtp(&options(false, true), false));
+    }
+
+    #[test]
+    fn srtp_parameters_match_encryption() {
+        let options = options(true, true);
+
+        assert!(params(true, None, None).valid_for_rtp(&options, true));
+        assert!(!params(false, None, None).valid_for_rtp(&options, true));
+        assert!(!params(true, None, None).valid_for_rtp(&options, false));
+    }
+
+    #[test]
+    fn webrtc_params_rejected() {
+        let params = ConnectTransportParams::WebRtc {
+            dtls_parameters: DtlsParameters {
+                role: DtlsRole::Auto,
+                fingerprints: Vec::new(),
+            },
+        };
+
+        assert!(!params.valid_for_rtp(&options(true, true), false));
+    }
 }
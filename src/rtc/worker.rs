@@ -1,38 +1,310 @@
-use mediasoup::{worker::Worker, worker::WorkerSettings, worker_manager::WorkerManager};
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use mediasoup::data_structures::{ListenInfo, Protocol};
+use mediasoup::router::NewTransport;
+use mediasoup::webrtc_server::{WebRtcServer, WebRtcServerListenInfos, WebRtcServerOptions};
+use mediasoup::worker::{CreateWebRtcServerError, Worker, WorkerId, WorkerSettings};
+use mediasoup::worker_manager::WorkerManager;
 use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::{self, UnboundedSender};
 
-use crate::util::variables::{RTC_MAX_PORT, RTC_MIN_PORT};
+use crate::state::room::Room;
+use crate::util::variables::{RTC_IPS, RTC_MAX_PORT, RTC_MIN_PORT, RTC_SERVER_PORT, RTC_WORKERS};
 
 pub static WORKER_POOL: OnceCell<WorkerPool> = OnceCell::new();
 
+/// How often spawning a replacement for a dead worker is attempted before giving up on its slot
+const RESPAWN_ATTEMPTS: u32 = 5;
+/// Delay before the next respawn attempt, multiplied by the number of failed attempts
+const RESPAWN_DELAY: Duration = Duration::from_secs(1);
+
 pub fn get_worker_pool() -> &'static WorkerPool {
     WORKER_POOL
         .get()
         .expect("Mediasoup worker pool not initialized")
 }
 
-// Single threaded for now
 #[derive(Debug)]
-pub struct WorkerPool {
-    //manager: WorkerManager,
+pub enum PoolWorkerError {
+    Worker(io::Error),
+    WebRtcServer(CreateWebRtcServerError),
+    PortOutOfRange,
+}
+
+impl Display for PoolWorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolWorkerError::Worker(err) => write!(f, "Failed to spawn worker: {}", err),
+            PoolWorkerError::WebRtcServer(err) => {
+                write!(f, "Failed to create WebRTC server: {}", err)
+            }
+            PoolWorkerError::PortOutOfRange => {
+                write!(f, "WebRTC server port exceeds the highest port number")
+            }
+        }
+    }
+}
+
+/// A mediasoup worker together with the load it is currently handling
+#[derive(Debug)]
+pub struct PoolWorker {
+    /// Position of the worker in the pool, kept by its replacement
+    index: usize,
     worker: Worker,
+    webrtc_server: Option<WebRtcServer>,
+    routers: Arc<AtomicUsize>,
+    transports: Arc<AtomicUsize>,
+}
+
+impl PoolWorker {
+    /// Spawn a worker, `index` is its position in the pool and decides the WebRTC server port.
+    /// The worker's ID is sent to `dead_sender` if it dies
+    async fn new(
+        manager: &WorkerManager,
+        index: usize,
+        dead_sender: &UnboundedSender<WorkerId>,
+    ) -> Result<Self, PoolWorkerError> {
+        let mut settings = WorkerSettings::default();
+        settings.rtc_ports_range = (*RTC_MIN_PORT)..=(*RTC_MAX_PORT);
+
+        let worker = manager
+            .create_worker(settings)
+            .await
+            .map_err(PoolWorkerError::Worker)?;
+        let worker_id = worker.id();
+        let dead_sender = dead_sender.clone();
+        worker
+            .on_dead(move |reason| {
+                error!("Mediasoup worker {} died: {:?}", worker_id, reason);
+                dead_sender.send(worker_id).ok();
+            })
+            .detach();
+
+        let webrtc_server = match *RTC_SERVER_PORT {
+            Some(port) => {
+                let port = u16::try_from(index)
+                    .ok()
+                    .and_then(|index| port.checked_add(index))
+                    .ok_or(PoolWorkerError::PortOutOfRange)?;
+                let server = worker
+                    .create_webrtc_server(WebRtcServerOptions::new(webrtc_server_listen_infos(
+                        port,
+                    )))
+                    .await
+                    .map_err(PoolWorkerError::WebRtcServer)?;
+                debug!("Worker {} listening on port {}", worker_id, port);
+                Some(server)
+            }
+            None => None,
+        };
+
+        let routers = Arc::new(AtomicUsize::new(0));
+        let transports = Arc::new(AtomicUsize::new(0));
+
+        {
+            let routers = routers.clone();
+            let transports = transports.clone();
+            worker
+                .on_new_router(move |router| {
+                    routers.fetch_add(1, Ordering::Relaxed);
+                    {
+                        let routers = routers.clone();
+                        router
+                            .on_close(move || {
+                                routers.fetch_sub(1, Ordering::Relaxed);
+                            })
+                            .detach();
+                    }
+
+                    let transports = transports.clone();
+                    router
+                        .on_new_transport(move |transport: NewTransport<'_>| {
+                            transports.fetch_add(1, Ordering::Relaxed);
+                            let transports = transports.clone();
+                            transport
+                                .on_close(Box::new(move || {
+                                    transports.fetch_sub(1, Ordering::Relaxed);
+                                }))
+                                .detach();
+                        })
+                        .detach();
+                })
+                .detach();
+        }
+
+        Ok(PoolWorker {
+            index,
+            worker,
+            webrtc_server,
+            routers,
+            transports,
+        })
+    }
+
+    pub fn worker(&self) -> &Worker {
+        &self.worker
+    }
+
+    pub fn webrtc_server(&self) -> Option<&WebRtcServer> {
+        self.webrtc_server.as_ref()
+    }
+
+    pub fn routers(&self) -> usize {
+        self.routers.load(Ordering::Relaxed)
+    }
+
+    pub fn transports(&self) -> usize {
+        self.transports.load(Ordering::Relaxed)
+    }
+
+    /// Sort key used to pick the least loaded worker, lower is better
+    fn load(&self) -> (usize, usize) {
+        (self.transports(), self.routers())
+    }
+}
+
+#[derive(Debug)]
+pub struct WorkerPool {
+    manager: WorkerManager,
+    workers: RwLock<Vec<Arc<PoolWorker>>>,
+    dead_sender: UnboundedSender<WorkerId>,
 }
 
 impl WorkerPool {
     pub async fn new() -> Self {
         let manager = WorkerManager::new();
-        let mut settings = WorkerSettings::default();
-        settings.rtc_ports_range = (*RTC_MIN_PORT)..=(*RTC_MAX_PORT);
+        let (dead_sender, mut dead_receiver) = mpsc::unbounded_channel();
+
+        let mut workers = Vec::with_capacity(*RTC_WORKERS);
+        for index in 0..*RTC_WORKERS {
+            let worker = PoolWorker::new(&manager, index, &dead_sender)
+                .await
+                .unwrap_or_else(|err| panic!("Failed to initialize worker pool: {}", err));
+            workers.push(Arc::new(worker));
+        }
 
-        let worker = manager.create_worker(settings).await.unwrap();
-        debug!("Initialized worker pool");
+        // Replacements run on their own task, spawning them from `on_dead` would make
+        // the future of `PoolWorker::new` depend on itself to be Send
+        tokio::spawn(async move {
+            while let Some(dead_id) = dead_receiver.recv().await {
+                match WORKER_POOL.get() {
+                    Some(pool) => pool.replace_worker(dead_id).await,
+                    None => error!("Worker died before the worker pool was initialized"),
+                }
+            }
+        });
+
+        debug!("Initialized worker pool with {} workers", workers.len());
         WorkerPool {
-            //manager,
-            worker,
+            manager,
+            workers: RwLock::new(workers),
+            dead_sender,
         }
     }
 
-    pub fn get_worker(&self) -> &Worker {
-        &self.worker
+    /// Get the least loaded worker, used when placing a new room.
+    /// Only empty if every worker died and could not be replaced
+    pub fn get_worker(&self) -> Option<Arc<PoolWorker>> {
+        self.workers()
+            .into_iter()
+            .min_by_key(|worker| worker.load())
     }
+
+    /// Get the least loaded worker not in `used`, used when spreading a room over several
+    /// workers. Falls back to the least loaded worker if the room already uses all of them
+    pub fn get_spare_worker(&self, used: &[WorkerId]) -> Option<Arc<PoolWorker>> {
+        self.workers()
+            .into_iter()
+            .min_by_key(|worker| (used.contains(&worker.worker.id()), worker.load()))
+    }
+
+    pub fn workers(&self) -> Vec<Arc<PoolWorker>> {
+        self.workers.read().unwrap().clone()
+    }
+
+    /// Replace a dead worker with a freshly spawned one and recover the rooms it hosted.
+    /// If no replacement can be spawned the slot is dropped and the other workers take over
+    async fn replace_worker(&self, dead_id: WorkerId) {
+        let index = match self.workers().iter().find(|w| w.worker.id() == dead_id) {
+            Some(worker) => worker.index,
+            None => return,
+        };
+
+        let mut replacement = None;
+        for attempt in 1..=RESPAWN_ATTEMPTS {
+            // The error is not Send, so it must be dropped before sleeping
+            match PoolWorker::new(&self.manager, index, &self.dead_sender).await {
+                Ok(worker) => {
+                    replacement = Some(Arc::new(worker));
+                    break;
+                }
+                Err(err) => error!(
+                    "Failed to replace dead worker {} (attempt {}/{}): {}",
+                    dead_id, attempt, RESPAWN_ATTEMPTS, err
+                ),
+            }
+
+            if attempt < RESPAWN_ATTEMPTS {
+                tokio::time::sleep(RESPAWN_DELAY * attempt).await;
+            }
+        }
+
+        let worker = {
+            let mut workers = self.workers.write().unwrap();
+            let position = match workers.iter().position(|w| w.worker.id() == dead_id) {
+                Some(position) => position,
+                None => return,
+            };
+
+            match replacement {
+                Some(worker) => {
+                    workers[position] = worker.clone();
+                    info!(
+                        "Replaced dead worker {} with worker {}",
+                        dead_id,
+                        worker.worker.id()
+                    );
+                    Some(worker)
+                }
+                None => {
+                    workers.remove(position);
+                    error!(
+                        "Gave up on replacing dead worker {}, {} workers left",
+                        dead_id,
+                        workers.len()
+                    );
+                    workers.iter().min_by_key(|worker| worker.load()).cloned()
+                }
+            }
+        };
+
+        Room::recover_worker(dead_id, worker).await;
+    }
+}
+
+/// UDP and TCP listen infos on a single port for every configured IP
+fn webrtc_server_listen_infos(port: u16) -> WebRtcServerListenInfos {
+    let mut listen_infos = RTC_IPS.iter().flat_map(|udp| {
+        let udp = ListenInfo {
+            port: Some(port),
+            ..*udp
+        };
+        let tcp = ListenInfo {
+            protocol: Protocol::Tcp,
+            ..udp
+        };
+        vec![udp, tcp]
+    });
+
+    let first = listen_infos
+        .next()
+        .expect("RTC_IPS must contain at least one IP");
+    listen_infos.fold(WebRtcServerListenInfos::new(first), |infos, info| {
+        infos.insert(info)
+    })
 }
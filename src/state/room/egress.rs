@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use mediasoup::prelude::*;
+use mediasoup::srtp_parameters::SrtpParameters;
+use serde::{Deserialize, Serialize};
+
+use super::RoomOptions;
+use crate::state::user::ProduceType;
+
+/// Options passed when starting an egress over the management API
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressOptions {
+    /// Address the RTP streams are sent to
+    pub ip: IpAddr,
+    pub port: u16,
+    /// Port RTCP is sent to, RTCP is multiplexed with RTP if not set
+    #[serde(default)]
+    pub rtcp_port: Option<u16>,
+    /// Parameters of the remote end, enables SRTP if set
+    #[serde(default)]
+    pub srtp_parameters: Option<SrtpParameters>,
+    /// Only forward the producers of these users, defaults to every user
+    #[serde(default)]
+    pub users: Option<HashSet<String>>,
+    /// Types of producers to forward
+    #[serde(default = "default_types")]
+    pub types: HashSet<ProduceType>,
+}
+
+fn default_types() -> HashSet<ProduceType> {
+    HashSet::from([ProduceType::Audio])
+}
+
+/// Egress information returned over the management API
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressInfo {
+    id: String,
+    ip: IpAddr,
+    port: u16,
+    rtcp_port: Option<u16>,
+    /// Parameters the remote end has to decrypt the streams with
+    srtp_parameters: Option<SrtpParameters>,
+    consumers: Vec<EgressConsumerInfo>,
+}
+
+impl EgressInfo {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressConsumerInfo {
+    user_id: String,
+    #[serde(rename = "type")]
+    produce_type: ProduceType,
+    producer_id: ProducerId,
+    kind: MediaKind,
+    rtp_parameters: RtpParameters,
+}
+
+struct EgressConsumer {
+    user_id: String,
+    produce_type: ProduceType,
+    consumer: Consumer,
+}
+
+/// Forwarding of a room's producers over a plain transport to an external destination
+pub struct Egress {
+    options: EgressOptions,
+    /// Capabilities the producers are consumed with, matching the codecs of the room
+    rtp_capabilities: RtpCapabilities,
+    transport: PlainTransport,
+    consumers: Vec<EgressConsumer>,
+}
+
+impl Egress {
+    /// Create the plain transport on `router` and connect it to the destination
+    pub async fn new(
+        router: &Router,
+        room_options: &RoomOptions,
+        options: EgressOptions,
+    ) -> Result<Self, ()> {
+        let listen_info = crate::rtc::plain_listen_info(options.ip).ok_or(())?;
+        let mut transport_options = PlainTransportOptions::new(listen_info);
+        transport_options.rtcp_mux = options.rtcp_port.is_none();
+        transport_options.comedia = false;
+        if let Some(srtp_parameters) = &options.srtp_parameters {
+            transport_options.enable_srtp = true;
+            transport_options.srtp_crypto_suite = srtp_parameters.crypto_suite;
+        }
+
+        let transport = router
+            .create_plain_transport(transport_options)
+            .await
+            .map_err(|_| ())?;
+        transport
+            .connect(PlainTransportRemoteParameters {
+                ip: Some(options.ip),
+                port: Some(options.port),
+                rtcp_port: options.rtcp_port,
+                srtp_parameters: options.srtp_parameters.clone(),
+            })
+            .await
+            .map_err(|_| ())?;
+
+        Ok(Egress {
+            options,
+            rtp_capabilities: egress_capabilities(router, room_options),
+            transport,
+            consumers: Vec::new(),
+        })
+    }
+
+    pub fn id(&self) -> String {
+        self.transport.id().to_string()
+    }
+
+    pub fn closed(&self) -> bool {
+        self.transport.closed()
+    }
+
+    /// Whether a user's producer is selected to be forwarded
+    pub fn selects(&self, user_id: &str, produce_type: ProduceType) -> bool {
+        let user_selected = match &self.options.users {
+            Some(users) => users.contains(user_id),
+            None => true,
+        };
+
+        user_selected && self.options.types.contains(&produce_type)
+    }
+
+    /// Forward a producer which is available on the egress transport's router
+    pub async fn add_producer(
+        &mut self,
+        user_id: &str,
+        produce_type: ProduceType,
+        producer_id: ProducerId,
+    ) -> Result<(), ()> {
+        let consumer = self
+            .transport
+            .consume(ConsumerOptions::new(
+                producer_id,
+                self.rtp_capabilities.clone(),
+            ))
+            .await
+            .map_err(|_| ())?;
+
+        self.consumers
+            .retain(|consumer| !consumer.consumer.closed());
+        self.consumers.push(EgressConsumer {
+            user_id: user_id.to_string(),
+            produce_type,
+            consumer,
+        });
+        Ok(())
+    }
+
+    pub fn into_info(&self) -> EgressInfo {
+        EgressInfo {
+            id: self.id(),
+            ip: self.options.ip,
+            port: self.options.port,
+            rtcp_port: self.options.rtcp_port,
+            srtp_parameters: self.transport.srtp_parameters(),
+            consumers: self
+                .consumers
+                .iter()
+                .filter(|consumer| !consumer.consumer.closed())
+                .map(|consumer| EgressConsumerInfo {
+                    user_id: consumer.user_id.clone(),
+                    produce_type: consumer.produce_type,
+                    producer_id: consumer.consumer.producer_id(),
+                    kind: consumer.consumer.kind(),
+                    rtp_parameters: consumer.consumer.rtp_parameters().clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Every codec the routers of a room may produce with, and the header extensions of the room
+fn egress_capabilities(router: &Router, room_options: &RoomOptions) -> RtpCapabilities {
+    let mut codecs = vec![crate::rtc::create_opus_codec(room_options.audio_profile)];
+    if room_options.video_codecs() {
+        codecs.extend(crate::rtc::create_video_codecs());
+    }
+
+    let mut header_extensions = router.rtp_capabilities().header_extensions.clone();
+    crate::rtc::filter_header_extensions(&mut header_extensions, room_options.audio_profile);
+    RtpCapabilities {
+        codecs,
+        header_extensions,
+    }
+}
@@ -1,27 +1,59 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU8;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex as SyncMutex, RwLock as SyncRwLock,
 };
+use std::time::{Duration, Instant};
 
-use mediasoup::router::{Router, RouterOptions};
+use mediasoup::data_producer::DataProducerId;
+use mediasoup::producer::{Producer, ProducerId};
+use mediasoup::router::{
+    PipeDataProducerToRouterPair, PipeProducerToRouterPair, PipeToRouterOptions, Router, RouterId,
+};
+use mediasoup::rtp_parameters::RtpCapabilitiesFinalized;
+use mediasoup::worker::WorkerId;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{
     broadcast::{self, Receiver, Sender},
-    RwLock,
+    Mutex, RwLock,
 };
 
 use super::user::{ProduceType, User};
-use crate::{api::ApiError, rtc::get_worker_pool};
+use crate::rtc::{get_worker_pool, worker::PoolWorker};
+use crate::{api::ApiError, util::variables::CLOSE_ROOMS_ON_CRASH};
 
+pub mod egress;
+pub mod recording;
+pub mod router;
 pub mod users;
+pub use egress::{Egress, EgressInfo, EgressOptions};
+pub use recording::Recording;
+pub use router::RoomRouter;
 pub use users::RoomUsers;
 
 #[derive(Clone, Debug)]
 pub enum RoomEvent {
     UserJoined(String),
     UserLeft(String),
+    /// Sent before the user is removed, with the reason given by the moderator
+    UserKicked(String, Option<String>),
     UserStartProduce(String, ProduceType),
     UserStopProduce(String, ProduceType),
+    UserProducerPaused(String, ProduceType),
+    UserProducerResumed(String, ProduceType),
+    UserServerMuted(String, bool),
+    UserServerDeafened(String, bool),
+    UserStartDataProduce(String, DataProducerId, String),
+    UserStopDataProduce(String, DataProducerId),
+    UserSpeaking(String, i8),
+    UserSilent(String),
+    /// No ID once the dominant speaker stopped producing audio or left
+    DominantSpeaker(Option<String>),
+    RecordingStarted,
+    RecordingStopped,
+    /// Sent to the users whose router was on a dead worker, they have to reinitialize
+    RouterRecreated(HashSet<String>),
     RoomDelete,
 }
 
@@ -31,11 +63,143 @@ lazy_static! {
 
 pub type RoomUserMap = HashMap<String, RwLock<User>>;
 pub type RoomRegistrationMap = HashMap<String, String>;
+pub type RoomPipeMap = HashMap<(ProducerId, RouterId), PipeProducerToRouterPair>;
+pub type RoomDataPipeMap = HashMap<(DataProducerId, RouterId), PipeDataProducerToRouterPair>;
+
+/// Options passed when creating a room over the management API
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomOptions {
+    /// Spread users over routers on other workers once a router hosts this many users
+    #[serde(default)]
+    pub users_per_router: Option<usize>,
+    /// Whether users may produce video from their camera
+    #[serde(default)]
+    pub video_allowed: bool,
+    /// Who may share their screen in this room
+    #[serde(default)]
+    pub screenshare: ScreensharePolicy,
+    /// Volume in dBvo (-127 to 0) above which a user is reported as speaking
+    #[serde(default)]
+    pub speaking_threshold: Option<i8>,
+    /// Interval in ms (250 to 5000) at which speaking users are reported
+    #[serde(default)]
+    pub speaking_interval: Option<u16>,
+    /// Opus settings used by the room
+    #[serde(default)]
+    pub audio_profile: AudioProfile,
+    /// Priorities consumers are created with, deciding who gets bandwidth first
+    #[serde(default)]
+    pub consumer_priorities: ConsumerPriorities,
+    /// Limit in bps of what a single user may send
+    #[serde(default)]
+    pub max_incoming_bitrate: Option<u32>,
+    /// Limit in bps of what is sent to a single user, only applied to WebRTC transports
+    #[serde(default)]
+    pub max_outgoing_bitrate: Option<u32>,
+}
+
+/// Priorities (1 to 255) of consumers depending on what they consume, higher is more important
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerPriorities {
+    pub screenshare: NonZeroU8,
+    /// Video of the current dominant speaker
+    pub speaker_video: NonZeroU8,
+    pub other: NonZeroU8,
+}
+
+impl Default for ConsumerPriorities {
+    fn default() -> Self {
+        ConsumerPriorities {
+            screenshare: NonZeroU8::new(3).unwrap(),
+            speaker_video: NonZeroU8::new(2).unwrap(),
+            other: NonZeroU8::new(1).unwrap(),
+        }
+    }
+}
+
+/// Opus settings of a room, tuned for the kind of audio being sent
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AudioProfile {
+    /// Opus defaults, left to what the clients negotiate
+    Standard,
+    /// Mono speech with FEC and DTX
+    Voice,
+    /// Stereo at a high bitrate, without DTX so quiet passages aren't cut
+    Music,
+    /// Narrow band speech for constrained connections
+    LowBandwidth,
+}
+
+impl Default for AudioProfile {
+    fn default() -> Self {
+        AudioProfile::Standard
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ScreensharePolicy {
+    Everyone,
+    Nobody,
+    /// Only the listed user IDs may share their screen
+    Users(HashSet<String>),
+}
+
+impl Default for ScreensharePolicy {
+    fn default() -> Self {
+        ScreensharePolicy::Everyone
+    }
+}
+
+impl RoomOptions {
+    /// Check the options against the ranges mediasoup accepts
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if let Some(threshold) = self.speaking_threshold {
+            if !(-127..=0).contains(&threshold) {
+                return Err(ApiError::InvalidOptions(
+                    "speakingThreshold must be between -127 and 0".to_string(),
+                ));
+            }
+        }
+
+        if let Some(interval) = self.speaking_interval {
+            if !(250..=5000).contains(&interval) {
+                return Err(ApiError::InvalidOptions(
+                    "speakingInterval must be between 250 and 5000".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the room's routers need video codecs
+    pub(super) fn video_codecs(&self) -> bool {
+        match self.screenshare {
+            ScreensharePolicy::Nobody => self.video_allowed,
+            _ => true,
+        }
+    }
+}
 
 pub struct Room {
     id: String,
+    options: RoomOptions,
     closed: AtomicBool,
-    router: Router,
+    /// Routers used by this room and the workers they live on, the first one is the primary router
+    routers: SyncRwLock<Vec<RoomRouter>>,
+    /// Producers piped from the router they were created on to another router of this room
+    pipes: Mutex<RoomPipeMap>,
+    data_pipes: Mutex<RoomDataPipeMap>,
+    dominant_speaker: Arc<SyncMutex<Option<String>>>,
+    recording: Mutex<Option<Recording>>,
+    /// Forwardings of the room to external destinations, by ID
+    egresses: Mutex<HashMap<String, Egress>>,
+    /// Users who may not rejoin until the given time
+    bans: SyncMutex<HashMap<String, Instant>>,
     sender: Sender<RoomEvent>,
 
     users: RwLock<RoomUserMap>,
@@ -43,26 +207,31 @@ pub struct Room {
 }
 
 impl Room {
-    pub async fn new(id: String /* video_allowed: bool */) -> Result<Arc<Self>, ApiError> {
+    pub async fn new(id: String, options: RoomOptions) -> Result<Arc<Self>, ApiError> {
         if ROOMS.read().await.contains_key(&id) {
             return Err(ApiError::RoomAlreadyExists(id));
         }
 
-        let worker = get_worker_pool().get_worker();
+        let (sender, _) = broadcast::channel(128);
+        let dominant_speaker = Arc::new(SyncMutex::new(None));
+        let worker = get_worker_pool()
+            .get_worker()
+            .ok_or(ApiError::InternalServerError)?;
+        let router =
+            RoomRouter::new(&worker, &options, sender.clone(), dominant_speaker.clone()).await?;
 
-        let mut options = RouterOptions::default();
-        options.media_codecs.push(crate::rtc::create_opus_codec(2));
-        let router = worker
-            .create_router(options)
-            .await
-            .map_err(|_| ApiError::InternalServerError)?;
-
-        let (sender, _) = broadcast::channel(32);
         info!("Created new room {}", id);
         let room = Arc::new(Room {
             id: id.clone(),
+            options,
             closed: AtomicBool::new(false),
-            router,
+            routers: SyncRwLock::new(vec![router]),
+            pipes: Mutex::new(HashMap::new()),
+            data_pipes: Mutex::new(HashMap::new()),
+            dominant_speaker,
+            recording: Mutex::new(None),
+            egresses: Mutex::new(HashMap::new()),
+            bans: SyncMutex::new(HashMap::new()),
             sender,
 
             users: RwLock::new(HashMap::new()),
@@ -74,6 +243,263 @@ impl Room {
         Ok(room)
     }
 
+    async fn create_router(&self, worker: &PoolWorker) -> Result<RoomRouter, ApiError> {
+        RoomRouter::new(
+            worker,
+            &self.options,
+            self.sender.clone(),
+            self.dominant_speaker.clone(),
+        )
+        .await
+    }
+
+    /// Recover every room which had a router on a worker that died, rooms are closed
+    /// if there is no worker left to move them to
+    pub async fn recover_worker(dead_id: WorkerId, worker: Option<Arc<PoolWorker>>) {
+        let rooms: Vec<Arc<Room>> = ROOMS
+            .read()
+            .await
+            .values()
+            .filter(|room| room.uses_worker(dead_id))
+            .cloned()
+            .collect();
+
+        for room in rooms {
+            let worker = match &worker {
+                Some(worker) if !*CLOSE_ROOMS_ON_CRASH => worker,
+                _ => {
+                    warn!("Closing room {} after its worker died", room.id);
+                    room.delete().await;
+                    continue;
+                }
+            };
+
+            let primary_dead = room.routers.read().unwrap()[0].worker_id() == dead_id;
+            let primary = match primary_dead {
+                true => match room.create_router(worker).await {
+                    Ok(router) => Some(router),
+                    Err(_) => {
+                        error!("Failed to recreate router for room {}, closing it", room.id);
+                        room.delete().await;
+                        continue;
+                    }
+                },
+                false => None,
+            };
+
+            room.reset_routers(dead_id, primary).await;
+        }
+    }
+
+    /// Drop every router on a dead worker, swapping in a new primary router if needed, and
+    /// the producers of the users on those routers since they have to reinitialize their transports
+    async fn reset_routers(&self, dead_id: WorkerId, primary: Option<RoomRouter>) {
+        let alive: HashSet<RouterId> = {
+            let mut routers = self.routers.write().unwrap();
+            if let Some(primary) = primary {
+                routers[0] = primary;
+            }
+            routers.retain(|router| router.worker_id() != dead_id);
+            routers.iter().map(|router| router.router().id()).collect()
+        };
+        // Pipes between the surviving routers keep working
+        self.pipes
+            .lock()
+            .await
+            .retain(|(_, target), pair| alive.contains(target) && !pair.pipe_consumer.closed());
+        self.data_pipes.lock().await.retain(|(_, target), pair| {
+            alive.contains(target) && !pair.pipe_data_consumer.closed()
+        });
+        // Egress transports live on the primary router, they are gone if it was recreated
+        self.egresses
+            .lock()
+            .await
+            .retain(|_, egress| !egress.closed());
+
+        let mut affected = HashSet::new();
+        let users = self.users.read().await;
+        for user in users.values() {
+            let mut user = user.write().await;
+            match user.router() {
+                Some(router) if router.worker().id() == dead_id => {}
+                _ => continue,
+            }
+
+            affected.insert(user.id().to_string());
+            let (producers, data_producers) = user.clear_producers();
+            for produce_type in producers {
+                self.send_event(RoomEvent::UserStopProduce(
+                    user.id().to_string(),
+                    produce_type,
+                ));
+            }
+            for data_producer_id in data_producers {
+                self.send_event(RoomEvent::UserStopDataProduce(
+                    user.id().to_string(),
+                    data_producer_id,
+                ));
+            }
+        }
+        drop(users);
+
+        info!(
+            "Recreated routers for room {}, {} users have to reinitialize",
+            self.id,
+            affected.len()
+        );
+        self.send_event(RoomEvent::RouterRecreated(affected));
+    }
+
+    /// Pick the router a user's transports should be created on, spawning
+    /// a new router on another worker if every router of the room is full
+    pub async fn assign_router(&self) -> Result<RoomRouter, ApiError> {
+        if self.closed() {
+            return Err(ApiError::InternalServerError);
+        }
+
+        let limit = match self.options.users_per_router {
+            Some(limit) => limit,
+            None => return Ok(self.routers.read().unwrap()[0].clone()),
+        };
+
+        let mut counts: HashMap<RouterId, usize> = HashMap::new();
+        for user in self.users.read().await.values() {
+            if let Some(router) = user.read().await.router() {
+                *counts.entry(router.id()).or_insert(0) += 1;
+            }
+        }
+
+        let free = self
+            .routers
+            .read()
+            .unwrap()
+            .iter()
+            .find(|router| counts.get(&router.router().id()).copied().unwrap_or(0) < limit)
+            .cloned();
+
+        if let Some(router) = free {
+            return Ok(router);
+        }
+
+        let used: Vec<WorkerId> = self
+            .routers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|router| router.worker_id())
+            .collect();
+        let worker = get_worker_pool()
+            .get_spare_worker(&used)
+            .ok_or(ApiError::InternalServerError)?;
+        let router = self.create_router(&worker).await?;
+        debug!(
+            "Added router {} on worker {} to room {}",
+            router.router().id(),
+            router.worker_id(),
+            self.id
+        );
+        self.routers.write().unwrap().push(router.clone());
+        Ok(router)
+    }
+
+    /// Report the audio level of a user's microphone producer living on `router`, and its
+    /// dominance on the primary router, piping it there if needed
+    pub async fn add_audio_producer(
+        &self,
+        router: &Router,
+        user_id: &str,
+        producer: &Producer,
+    ) -> Result<(), ()> {
+        let (room_router, primary) = {
+            let routers = self.routers.read().unwrap();
+            let room_router = routers
+                .iter()
+                .find(|room_router| room_router.router().id() == router.id())
+                .cloned()
+                .ok_or(())?;
+            (room_router, routers.first().cloned().ok_or(())?)
+        };
+
+        // The observer only picks a new dominant speaker among the remaining producers
+        let dominant_speaker = self.dominant_speaker.clone();
+        let sender = self.sender.clone();
+        let speaker_id = user_id.to_string();
+        producer
+            .on_close(move || {
+                let mut dominant_speaker = dominant_speaker.lock().unwrap();
+                if dominant_speaker.as_ref() == Some(&speaker_id) {
+                    *dominant_speaker = None;
+                    sender.send(RoomEvent::DominantSpeaker(None)).ok();
+                }
+            })
+            .detach();
+
+        room_router.add_audio_producer(user_id, producer).await?;
+        if primary.router().id() != router.id() {
+            self.pipe_producer(producer.id(), router, primary.router())
+                .await?;
+        }
+        primary.add_dominance_producer(user_id, producer).await
+    }
+
+    /// Make a producer available on another router of this room, reusing an existing pipe
+    pub async fn pipe_producer(
+        &self,
+        producer_id: ProducerId,
+        source: &Router,
+        target: &Router,
+    ) -> Result<(), ()> {
+        let mut pipes = self.pipes.lock().await;
+        pipes.retain(|_, pair| !pair.pipe_consumer.closed());
+
+        let key = (producer_id, target.id());
+        if pipes.contains_key(&key) {
+            return Ok(());
+        }
+
+        // The piping future is not Send, so it is driven on a blocking thread instead
+        let source = source.clone();
+        let options = PipeToRouterOptions::new(target.clone());
+        let pair = tokio::task::spawn_blocking(move || {
+            futures::executor::block_on(source.pipe_producer_to_router(producer_id, options))
+                .map_err(|err| warn!("Failed to pipe producer {}: {}", producer_id, err))
+        })
+        .await
+        .map_err(|_| ())??;
+        pipes.insert(key, pair);
+        Ok(())
+    }
+
+    /// Make a data producer available on another router of this room, reusing an existing pipe
+    pub async fn pipe_data_producer(
+        &self,
+        data_producer_id: DataProducerId,
+        source: &Router,
+        target: &Router,
+    ) -> Result<(), ()> {
+        let mut pipes = self.data_pipes.lock().await;
+        pipes.retain(|_, pair| !pair.pipe_data_consumer.closed());
+
+        let key = (data_producer_id, target.id());
+        if pipes.contains_key(&key) {
+            return Ok(());
+        }
+
+        // Driven on a blocking thread for the same reason as in `pipe_producer`
+        let source = source.clone();
+        let options = PipeToRouterOptions::new(target.clone());
+        let pair = tokio::task::spawn_blocking(move || {
+            futures::executor::block_on(
+                source.pipe_data_producer_to_router(data_producer_id, options),
+            )
+            .map_err(|err| warn!("Failed to pipe data producer {}: {}", data_producer_id, err))
+        })
+        .await
+        .map_err(|_| ())??;
+        pipes.insert(key, pair);
+        Ok(())
+    }
+
     pub async fn get(id: &str) -> Option<Arc<Self>> {
         ROOMS.read().await.get(id).map(|arc| arc.clone())
     }
@@ -90,10 +516,330 @@ impl Room {
         if result.is_ok() {
             info!("Deleting room {}", self.id);
             ROOMS.write().await.remove(&self.id);
+            if let Some(recording) = self.recording.lock().await.take() {
+                if let Err(err) = recording.stop().await {
+                    error!("Failed to finish recording of room {}: {}", self.id, err);
+                }
+            }
+            self.egresses.lock().await.clear();
             self.send_event(RoomEvent::RoomDelete);
         }
     }
 
+    /// Start recording every audio producer in the room, including ones created later
+    pub async fn start_recording(&self) -> Result<(), ApiError> {
+        let mut guard = self.recording.lock().await;
+        if guard.is_some() {
+            return Err(ApiError::RecordingAlreadyStarted(self.id.clone()));
+        }
+
+        let mut recording = Recording::new(&self.id).await.map_err(|err| {
+            error!("Failed to start recording of room {}: {}", self.id, err);
+            ApiError::InternalServerError
+        })?;
+
+        for user in self.users.read().await.values() {
+            let user = user.read().await;
+            if let (Some(router), Some(producer)) =
+                (user.router(), user.get_producer(ProduceType::Audio))
+            {
+                if recording
+                    .add_producer(router, user.id(), producer)
+                    .await
+                    .is_err()
+                {
+                    error!("Failed to record user {} in room {}", user.id(), self.id);
+                }
+            }
+        }
+
+        *guard = Some(recording);
+        info!("Started recording room {}", self.id);
+        self.send_event(RoomEvent::RecordingStarted);
+        Ok(())
+    }
+
+    pub async fn stop_recording(&self) -> Result<(), ApiError> {
+        let recording = self
+            .recording
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| ApiError::RecordingNotStarted(self.id.clone()))?;
+
+        self.send_event(RoomEvent::RecordingStopped);
+        recording.stop().await.map_err(|err| {
+            error!("Failed to finish recording of room {}: {}", self.id, err);
+            ApiError::InternalServerError
+        })
+    }
+
+    /// Add a new audio producer to the running recording, if any
+    pub async fn record_producer(&self, router: &Router, user_id: &str, producer: &Producer) {
+        if let Some(recording) = self.recording.lock().await.as_mut() {
+            if recording
+                .add_producer(router, user_id, producer)
+                .await
+                .is_err()
+            {
+                error!("Failed to record user {} in room {}", user_id, self.id);
+            }
+        }
+    }
+
+    pub async fn recording(&self) -> bool {
+        self.recording.lock().await.is_some()
+    }
+
+    /// Start forwarding the selected producers of the room, including ones created later
+    pub async fn start_egress(&self, options: EgressOptions) -> Result<EgressInfo, ApiError> {
+        if crate::rtc::plain_listen_info(options.ip).is_none() {
+            return Err(ApiError::InvalidOptions(
+                "No RTC IP has the address family of the egress destination".to_string(),
+            ));
+        }
+
+        let primary = self
+            .router()
+            .ok_or_else(|| ApiError::RoomNotFound(self.id.clone()))?;
+        let mut egress = Egress::new(&primary, &self.options, options)
+            .await
+            .map_err(|_| {
+                error!("Failed to create egress transport for room {}", self.id);
+                ApiError::InternalServerError
+            })?;
+
+        let mut egresses = self.egresses.lock().await;
+        for user in self.users.read().await.values() {
+            let user = user.read().await;
+            let router = match user.router() {
+                Some(router) => router,
+                None => continue,
+            };
+
+            for (produce_type, producer) in user.producers() {
+                if egress.selects(user.id(), *produce_type) {
+                    self.add_egress_producer(
+                        &mut egress,
+                        &primary,
+                        router,
+                        user.id(),
+                        *produce_type,
+                        producer,
+                    )
+                    .await;
+                }
+            }
+        }
+
+        let info = egress.into_info();
+        info!("Started egress {} of room {}", info.id(), self.id);
+        egresses.insert(egress.id(), egress);
+        Ok(info)
+    }
+
+    pub async fn stop_egress(&self, id: &str) -> Result<(), ApiError> {
+        self.egresses
+            .lock()
+            .await
+            .remove(id)
+            .ok_or_else(|| ApiError::EgressNotFound(id.to_string()))?;
+
+        info!("Stopped egress {} of room {}", id, self.id);
+        Ok(())
+    }
+
+    pub async fn egresses(&self) -> Vec<EgressInfo> {
+        self.egresses
+            .lock()
+            .await
+            .values()
+            .map(Egress::into_info)
+            .collect()
+    }
+
+    /// Add a new producer to every running egress it is selected by
+    pub async fn egress_producer(
+        &self,
+        router: &Router,
+        user_id: &str,
+        produce_type: ProduceType,
+        producer: &Producer,
+    ) {
+        let primary = match self.router() {
+            Some(primary) => primary,
+            None => return,
+        };
+
+        for egress in self.egresses.lock().await.values_mut() {
+            if egress.selects(user_id, produce_type) {
+                self.add_egress_producer(egress, &primary, router, user_id, produce_type, producer)
+                    .await;
+            }
+        }
+    }
+
+    async fn add_egress_producer(
+        &self,
+        egress: &mut Egress,
+        primary: &Router,
+        router: &Router,
+        user_id: &str,
+        produce_type: ProduceType,
+        producer: &Producer,
+    ) {
+        if router.id() != primary.id()
+            && self
+                .pipe_producer(producer.id(), router, primary)
+                .await
+                .is_err()
+        {
+            error!("Failed to pipe producer of user {} for egress", user_id);
+            return;
+        }
+
+        if egress
+            .add_producer(user_id, produce_type, producer.id())
+            .await
+            .is_err()
+        {
+            error!(
+                "Failed to forward user {} to egress {} of room {}",
+                user_id,
+                egress.id(),
+                self.id
+            );
+        }
+    }
+
+    /// Remove a user from the room, optionally refusing to let them rejoin for a while
+    pub async fn kick_user(
+        self: &Arc<Room>,
+        user_id: &str,
+        reason: Option<String>,
+        ban: Option<Duration>,
+    ) -> Result<(), ApiError> {
+        if !self.users.read().await.contains_key(user_id) {
+            return Err(ApiError::UserNotFound(user_id.to_string()));
+        }
+
+        let banned_until = ban
+            .map(|ban| {
+                Instant::now()
+                    .checked_add(ban)
+                    .ok_or_else(|| ApiError::InvalidOptions("banDuration is too long".to_string()))
+            })
+            .transpose()?;
+        if let Some(banned_until) = banned_until {
+            self.bans
+                .lock()
+                .unwrap()
+                .insert(user_id.to_string(), banned_until);
+        }
+
+        info!("Kicking user {} from room {}", user_id, self.id);
+        self.send_event(RoomEvent::UserKicked(user_id.to_string(), reason));
+        self.users()
+            .remove(user_id)
+            .await
+            .map_err(|_| ApiError::UserNotFound(user_id.to_string()))
+    }
+
+    /// Whether a user is currently banned from this room
+    pub(super) fn banned(&self, user_id: &str) -> bool {
+        let mut bans = self.bans.lock().unwrap();
+        let now = Instant::now();
+        bans.retain(|_, until| *until > now);
+        bans.contains_key(user_id)
+    }
+
+    /// Mute a user's audio producers without them being able to resume them, or lift it
+    pub async fn set_user_muted(&self, user_id: &str, muted: bool) -> Result<(), ApiError> {
+        let users = self.users.read().await;
+        let user = users
+            .get(user_id)
+            .ok_or_else(|| ApiError::UserNotFound(user_id.to_string()))?;
+        user.write().await.set_server_muted(muted).await;
+        drop(users);
+
+        info!(
+            "Set server mute of user {} in room {} to {}",
+            user_id, self.id, muted
+        );
+        self.send_event(RoomEvent::UserServerMuted(user_id.to_string(), muted));
+        Ok(())
+    }
+
+    /// Pause every consumer of a user without them being able to resume them, or lift it
+    pub async fn set_user_deafened(&self, user_id: &str, deafened: bool) -> Result<(), ApiError> {
+        let users = self.users.read().await;
+        let user = users
+            .get(user_id)
+            .ok_or_else(|| ApiError::UserNotFound(user_id.to_string()))?;
+        user.write().await.set_server_deafened(deafened);
+        drop(users);
+
+        info!(
+            "Set server deafen of user {} in room {} to {}",
+            user_id, self.id, deafened
+        );
+        // The user's connection pauses or resumes its consumers when receiving this
+        self.send_event(RoomEvent::UserServerDeafened(user_id.to_string(), deafened));
+        Ok(())
+    }
+
+    /// ID of the user who was last detected as the main speaker
+    pub fn dominant_speaker(&self) -> Option<String> {
+        self.dominant_speaker.lock().unwrap().clone()
+    }
+
+    /// Forget the dominant speaker if it is the given user, used when they leave
+    pub(super) fn clear_dominant_speaker(&self, user_id: &str) {
+        let mut dominant_speaker = self.dominant_speaker.lock().unwrap();
+        if dominant_speaker.as_deref() == Some(user_id) {
+            *dominant_speaker = None;
+            self.send_event(RoomEvent::DominantSpeaker(None));
+        }
+    }
+
+    pub fn video_allowed(&self) -> bool {
+        self.options.video_allowed
+    }
+
+    pub fn audio_profile(&self) -> AudioProfile {
+        self.options.audio_profile
+    }
+
+    /// Priority a consumer of a user's producer is created with
+    pub fn consumer_priority(&self, user_id: &str, produce_type: ProduceType) -> NonZeroU8 {
+        let priorities = &self.options.consumer_priorities;
+        if produce_type.screenshare() {
+            priorities.screenshare
+        } else if produce_type == ProduceType::Video
+            && self.dominant_speaker().as_deref() == Some(user_id)
+        {
+            priorities.speaker_video
+        } else {
+            priorities.other
+        }
+    }
+
+    pub fn max_incoming_bitrate(&self) -> Option<u32> {
+        self.options.max_incoming_bitrate
+    }
+
+    pub fn max_outgoing_bitrate(&self) -> Option<u32> {
+        self.options.max_outgoing_bitrate
+    }
+
+    pub fn can_screenshare(&self, user_id: &str) -> bool {
+        match &self.options.screenshare {
+            ScreensharePolicy::Everyone => true,
+            ScreensharePolicy::Nobody => false,
+            ScreensharePolicy::Users(users) => users.contains(user_id),
+        }
+    }
+
     pub fn closed(&self) -> bool {
         self.closed.load(Ordering::Relaxed)
     }
@@ -109,13 +855,32 @@ impl Room {
         }
     }
 
-    pub fn router(&self) -> Option<&Router> {
+    /// RTP capabilities clients have to use in this room
+    pub fn rtp_capabilities(&self) -> Option<RtpCapabilitiesFinalized> {
+        let mut capabilities = self.router()?.rtp_capabilities().clone();
+        crate::rtc::filter_header_extensions(
+            &mut capabilities.header_extensions,
+            self.options.audio_profile,
+        );
+        Some(capabilities)
+    }
+
+    /// Get the primary router of the room
+    pub fn router(&self) -> Option<Router> {
         match self.closed() {
-            false => Some(&self.router),
+            false => Some(self.routers.read().unwrap()[0].router().clone()),
             true => None,
         }
     }
 
+    pub fn uses_worker(&self, worker_id: WorkerId) -> bool {
+        self.routers
+            .read()
+            .unwrap()
+            .iter()
+            .any(|router| router.worker_id() == worker_id)
+    }
+
     pub fn users(self: &Arc<Room>) -> RoomUsers {
         RoomUsers::from_room(self.clone())
     }
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use mediasoup::data_structures::{ListenInfo, Protocol};
+use mediasoup::prelude::*;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, watch, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+
+use super::AudioProfile;
+use crate::util::ogg::{opus_packet_samples, OggOpusWriter};
+use crate::util::variables::RECORDING_DIR;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+/// Payload type Opus is consumed with, only used between mediasoup and the recorder
+const OPUS_PAYLOAD_TYPE: u8 = 100;
+
+/// Sidecar manifest written next to the recorded files
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    room: String,
+    /// Unix timestamps in ms
+    started_at: u64,
+    stopped_at: u64,
+    users: HashMap<String, ManifestUser>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestUser {
+    file: String,
+    segments: Vec<Segment>,
+}
+
+/// Time span a user was recorded, in ms relative to the start of the recording
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct Segment {
+    joined_at: u64,
+    left_at: Option<u64>,
+}
+
+struct RecordedUser {
+    writer: Arc<AsyncMutex<OggOpusWriter>>,
+    file: String,
+    segments: Vec<Segment>,
+}
+
+type RecordedUsers = Arc<Mutex<HashMap<String, RecordedUser>>>;
+
+/// Recording of every audio producer in a room, one Ogg/Opus file per user
+pub struct Recording {
+    room_id: String,
+    directory: PathBuf,
+    started_at: SystemTime,
+    started: Instant,
+    users: RecordedUsers,
+    stop: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Recording {
+    pub async fn new(room_id: &str) -> io::Result<Self> {
+        let started_at = SystemTime::now();
+        let directory = Path::new(&*RECORDING_DIR).join(format!(
+            "{}-{}",
+            sanitize(room_id),
+            unix_millis(started_at)
+        ));
+        tokio::fs::create_dir_all(&directory).await?;
+
+        let (stop, _) = watch::channel(false);
+        Ok(Recording {
+            room_id: room_id.to_string(),
+            directory,
+            started_at,
+            started: Instant::now(),
+            users: Arc::default(),
+            stop,
+            tasks: Vec::new(),
+        })
+    }
+
+    /// Start recording a user's audio producer living on `router`
+    pub async fn add_producer(
+        &mut self,
+        router: &Router,
+        user_id: &str,
+        producer: &Producer,
+    ) -> Result<(), ()> {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).await.map_err(|_| ())?;
+        let port = socket.local_addr().map_err(|_| ())?.port();
+
+        let mut options = PlainTransportOptions::new(ListenInfo {
+            ip: LOCALHOST,
+            announced_ip: None,
+            protocol: Protocol::Udp,
+            port: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+        });
+        options.rtcp_mux = true;
+        options.comedia = false;
+        let transport = router
+            .create_plain_transport(options)
+            .await
+            .map_err(|_| ())?;
+        transport
+            .connect(PlainTransportRemoteParameters {
+                ip: Some(LOCALHOST),
+                port: Some(port),
+                rtcp_port: None,
+                srtp_parameters: None,
+            })
+            .await
+            .map_err(|_| ())?;
+
+        let consumer = transport
+            .consume(ConsumerOptions::new(
+                producer.id(),
+                recording_capabilities(),
+            ))
+            .await
+            .map_err(|_| ())?;
+
+        let (closed_sender, closed) = oneshot::channel();
+        consumer
+            .on_producer_close(move || {
+                closed_sender.send(()).ok();
+            })
+            .detach();
+
+        let joined_at = self.started.elapsed().as_millis() as u64;
+        // Only this method adds users, so the file can be created without holding the lock
+        if !self.users.lock().unwrap().contains_key(user_id) {
+            let file = format!("{}.ogg", sanitize(user_id));
+            let writer = OggOpusWriter::create(&self.directory.join(&file), 2)
+                .await
+                .map_err(|_| ())?;
+            self.users.lock().unwrap().insert(
+                user_id.to_string(),
+                RecordedUser {
+                    writer: Arc::new(AsyncMutex::new(writer)),
+                    file,
+                    segments: Vec::new(),
+                },
+            );
+        }
+
+        let writer = {
+            let mut users = self.users.lock().unwrap();
+            let user = users.get_mut(user_id).ok_or(())?;
+            user.segments.push(Segment {
+                joined_at,
+                left_at: None,
+            });
+            user.writer.clone()
+        };
+
+        debug!(
+            "Recording producer {} of user {} in room {}",
+            producer.id(),
+            user_id,
+            self.room_id
+        );
+        let task = RecordingTask {
+            socket,
+            writer,
+            // Opus always uses a 48kHz clock
+            offset: joined_at * 48,
+            started: self.started,
+            user_id: user_id.to_string(),
+            users: self.users.clone(),
+            stop: self.stop.subscribe(),
+            closed,
+            _transport: transport,
+            _consumer: consumer,
+        };
+        self.tasks.push(tokio::spawn(task.run()));
+
+        Ok(())
+    }
+
+    /// Stop every recording task, finish the files and write the manifest
+    pub async fn stop(self) -> io::Result<()> {
+        self.stop.send(true).ok();
+        for task in self.tasks {
+            task.await.ok();
+        }
+
+        let users: Vec<(String, ManifestUser, Arc<AsyncMutex<OggOpusWriter>>)> = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, user)| {
+                let manifest_user = ManifestUser {
+                    file: user.file.clone(),
+                    segments: user.segments.clone(),
+                };
+                (id.clone(), manifest_user, user.writer.clone())
+            })
+            .collect();
+
+        let mut manifest_users = HashMap::new();
+        for (id, manifest_user, writer) in users {
+            writer.lock().await.finish().await?;
+            manifest_users.insert(id, manifest_user);
+        }
+
+        let manifest = Manifest {
+            room: self.room_id.clone(),
+            started_at: unix_millis(self.started_at),
+            stopped_at: unix_millis(SystemTime::now()),
+            users: manifest_users,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+        tokio::fs::write(self.directory.join("manifest.json"), manifest).await?;
+
+        info!(
+            "Stopped recording room {} into {}",
+            self.room_id,
+            self.directory.display()
+        );
+        Ok(())
+    }
+}
+
+/// Receives the RTP stream of a single producer and writes it into the user's file
+struct RecordingTask {
+    socket: UdpSocket,
+    writer: Arc<AsyncMutex<OggOpusWriter>>,
+    /// Granule position the first packet is written at
+    offset: u64,
+    started: Instant,
+    user_id: String,
+    users: RecordedUsers,
+    stop: watch::Receiver<bool>,
+    closed: oneshot::Receiver<()>,
+    // Kept alive for as long as the task runs
+    _transport: PlainTransport,
+    _consumer: Consumer,
+}
+
+impl RecordingTask {
+    async fn run(mut self) {
+        let mut buffer = vec![0; 2048];
+        let mut first_timestamp = None;
+        let mut last_granule = 0;
+
+        loop {
+            tokio::select! {
+                result = self.socket.recv(&mut buffer) => {
+                    let len = match result {
+                        Ok(len) => len,
+                        Err(_) => break,
+                    };
+
+                    if let Some((timestamp, payload)) = rtp_payload(&buffer[..len]) {
+                        let first = *first_timestamp.get_or_insert(timestamp);
+                        let elapsed = timestamp.wrapping_sub(first);
+                        // Late packets from before the first one would wrap around, skip them
+                        if elapsed > u32::MAX / 2 {
+                            continue;
+                        }
+
+                        let granule = self.offset + elapsed as u64 + opus_packet_samples(payload);
+                        if granule <= last_granule {
+                            continue;
+                        }
+                        last_granule = granule;
+
+                        if let Err(err) = self.writer.lock().await.write_packet(payload, granule).await {
+                            error!("Failed to write recording of user {}: {}", self.user_id, err);
+                            break;
+                        }
+                    }
+                },
+                _ = self.stop.changed() => break,
+                _ = &mut self.closed => break,
+            }
+        }
+
+        let left_at = self.started.elapsed().as_millis() as u64;
+        if let Some(user) = self.users.lock().unwrap().get_mut(&self.user_id) {
+            if let Some(segment) = user.segments.iter_mut().find(|s| s.left_at.is_none()) {
+                segment.left_at = Some(left_at);
+            }
+        }
+    }
+}
+
+/// Extract the timestamp and payload of an RTP packet, ignoring RTCP
+fn rtp_payload(packet: &[u8]) -> Option<(u32, &[u8])> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+
+    // RTCP sender and receiver reports are multiplexed on the same port
+    if (200..=204).contains(&packet[1]) {
+        return None;
+    }
+
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let mut start = 12 + csrc_count * 4;
+    if packet[0] & 0x10 != 0 {
+        let header = packet.get(start..start + 4)?;
+        let words = u16::from_be_bytes([header[2], header[3]]) as usize;
+        start += 4 + words * 4;
+    }
+
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    let timestamp = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+    let payload = packet.get(start..end)?;
+    Some((timestamp, payload))
+}
+
+fn recording_capabilities() -> RtpCapabilities {
+    let mut codec = crate::rtc::create_opus_codec(AudioProfile::default());
+    if let RtpCodecCapability::Audio {
+        preferred_payload_type,
+        ..
+    } = &mut codec
+    {
+        *preferred_payload_type = Some(OPUS_PAYLOAD_TYPE);
+    }
+
+    RtpCapabilities {
+        codecs: vec![codec],
+        header_extensions: Vec::new(),
+    }
+}
+
+/// Strip anything that isn't safe to put in a file name
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(first: u8, payload_type: u8, extra_header: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![first, payload_type, 0, 1];
+        packet.extend_from_slice(&0x1234_5678u32.to_be_bytes());
+        packet.extend_from_slice(&[0; 4]); // SSRC
+        packet.extend_from_slice(extra_header);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn rtp_payload_plain() {
+        let packet = packet(0x80, OPUS_PAYLOAD_TYPE, &[], &[1, 2, 3]);
+        assert_eq!(rtp_payload(&packet), Some((0x1234_5678, &[1, 2, 3][..])));
+    }
+
+    #[test]
+    fn rtp_payload_skips_csrcs_and_extension() {
+        let mut extra_header = vec![0; 8]; // Two CSRCs
+        extra_header.extend_from_slice(&[0xbe, 0xde, 0, 1, 9, 9, 9, 9]);
+        let packet = packet(0x80 | 0x10 | 2, OPUS_PAYLOAD_TYPE, &extra_header, &[4, 5]);
+
+        assert_eq!(rtp_payload(&packet), Some((0x1234_5678, &[4, 5][..])));
+    }
+
+    #[test]
+    fn rtp_payload_strips_padding() {
+        let packet = packet(0x80 | 0x20, OPUS_PAYLOAD_TYPE, &[], &[6, 7, 0, 0, 3]);
+        assert_eq!(rtp_payload(&packet), Some((0x1234_5678, &[6, 7][..])));
+    }
+
+    #[test]
+    fn rtp_payload_rejects_invalid_packets() {
+        // Too short
+        assert_eq!(rtp_payload(&[0x80; 11]), None);
+        // Wrong version
+        assert_eq!(
+            rtp_payload(&packet(0x40, OPUS_PAYLOAD_TYPE, &[], &[1])),
+            None
+        );
+        // RTCP receiver report
+        assert_eq!(rtp_payload(&packet(0x80, 201, &[], &[1])), None);
+        // Extension header past the end of the packet
+        assert_eq!(
+            rtp_payload(&packet(0x90, OPUS_PAYLOAD_TYPE, &[], &[1])),
+            None
+        );
+        // Padding longer than the packet
+        assert_eq!(
+            rtp_payload(&packet(0xa0, OPUS_PAYLOAD_TYPE, &[], &[255])),
+            None
+        );
+    }
+}
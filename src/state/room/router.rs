@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU16;
+use std::sync::{Arc, Mutex};
+
+use mediasoup::active_speaker_observer::{ActiveSpeakerObserver, ActiveSpeakerObserverOptions};
+use mediasoup::audio_level_observer::{AudioLevelObserver, AudioLevelObserverOptions};
+use mediasoup::producer::{Producer, ProducerId};
+use mediasoup::router::{Router, RouterOptions};
+use mediasoup::rtp_observer::{RtpObserver, RtpObserverAddProducerOptions};
+use mediasoup::webrtc_server::WebRtcServer;
+use mediasoup::worker::WorkerId;
+use tokio::sync::broadcast::Sender;
+
+use super::{RoomEvent, RoomOptions};
+use crate::api::ApiError;
+use crate::rtc::worker::PoolWorker;
+
+/// Default volume in dBvo above which a user is considered speaking
+const SPEAKING_THRESHOLD: i8 = -70;
+/// Default interval in ms at which audio levels are reported
+const SPEAKING_INTERVAL: u16 = 800;
+
+/// Users currently speaking on a router, keyed by their audio producer
+#[derive(Default)]
+struct Speakers {
+    producers: HashMap<ProducerId, String>,
+    speaking: HashSet<String>,
+}
+
+/// A router used by a room, together with the observers attached to it
+#[derive(Clone)]
+pub struct RoomRouter {
+    worker_id: WorkerId,
+    router: Router,
+    webrtc_server: Option<WebRtcServer>,
+    audio_level_observer: AudioLevelObserver,
+    /// Only fed on the primary router, see `add_dominance_producer`
+    active_speaker_observer: ActiveSpeakerObserver,
+    speakers: Arc<Mutex<Speakers>>,
+}
+
+impl RoomRouter {
+    pub async fn new(
+        worker: &PoolWorker,
+        room_options: &RoomOptions,
+        sender: Sender<RoomEvent>,
+        dominant_speaker: Arc<Mutex<Option<String>>>,
+    ) -> Result<Self, ApiError> {
+        let mut options = RouterOptions::default();
+        options
+            .media_codecs
+            .push(crate::rtc::create_opus_codec(room_options.audio_profile));
+        if room_options.video_codecs() {
+            options
+                .media_codecs
+                .extend(crate::rtc::create_video_codecs());
+        }
+
+        let router = worker
+            .worker()
+            .create_router(options)
+            .await
+            .map_err(|_| ApiError::InternalServerError)?;
+
+        let mut options = AudioLevelObserverOptions::default();
+        options.max_entries = NonZeroU16::new(16).unwrap();
+        options.threshold = room_options
+            .speaking_threshold
+            .unwrap_or(SPEAKING_THRESHOLD);
+        options.interval = room_options.speaking_interval.unwrap_or(SPEAKING_INTERVAL);
+        let audio_level_observer = router
+            .create_audio_level_observer(options)
+            .await
+            .map_err(|_| ApiError::InternalServerError)?;
+
+        let active_speaker_observer = router
+            .create_active_speaker_observer(ActiveSpeakerObserverOptions::default())
+            .await
+            .map_err(|_| ApiError::InternalServerError)?;
+
+        let speakers: Arc<Mutex<Speakers>> = Arc::default();
+        {
+            let speakers = speakers.clone();
+            let sender = sender.clone();
+            active_speaker_observer
+                .on_dominant_speaker(move |dominant| {
+                    let speakers = speakers.lock().unwrap();
+                    if let Some(id) = speakers.producers.get(&dominant.producer.id()) {
+                        let mut dominant_speaker = dominant_speaker.lock().unwrap();
+                        if dominant_speaker.as_ref() != Some(id) {
+                            *dominant_speaker = Some(id.clone());
+                            sender
+                                .send(RoomEvent::DominantSpeaker(Some(id.clone())))
+                                .ok();
+                        }
+                    }
+                })
+                .detach();
+        }
+        {
+            let speakers = speakers.clone();
+            let sender = sender.clone();
+            audio_level_observer
+                .on_volumes(move |volumes| {
+                    let mut speakers = speakers.lock().unwrap();
+                    let mut speaking = HashSet::new();
+                    for volume in volumes {
+                        if let Some(id) = speakers.producers.get(&volume.producer.id()) {
+                            speaking.insert(id.clone());
+                            sender
+                                .send(RoomEvent::UserSpeaking(id.clone(), volume.volume))
+                                .ok();
+                        }
+                    }
+
+                    for id in speakers.speaking.difference(&speaking) {
+                        sender.send(RoomEvent::UserSilent(id.clone())).ok();
+                    }
+                    speakers.speaking = speaking;
+                })
+                .detach();
+        }
+        {
+            let speakers = speakers.clone();
+            audio_level_observer
+                .on_silence(move || {
+                    let mut speakers = speakers.lock().unwrap();
+                    for id in speakers.speaking.drain() {
+                        sender.send(RoomEvent::UserSilent(id)).ok();
+                    }
+                })
+                .detach();
+        }
+
+        Ok(RoomRouter {
+            worker_id: worker.worker().id(),
+            router,
+            webrtc_server: worker.webrtc_server().cloned(),
+            audio_level_observer,
+            active_speaker_observer,
+            speakers,
+        })
+    }
+
+    pub fn worker_id(&self) -> WorkerId {
+        self.worker_id
+    }
+
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    /// WebRTC server of the router's worker, if running in single port mode
+    pub fn webrtc_server(&self) -> Option<&WebRtcServer> {
+        self.webrtc_server.as_ref()
+    }
+
+    /// Start reporting the audio level of a user's microphone producer living on this router
+    pub async fn add_audio_producer(&self, user_id: &str, producer: &Producer) -> Result<(), ()> {
+        self.track_speaker(user_id, producer);
+        self.audio_level_observer
+            .add_producer(RtpObserverAddProducerOptions::new(producer.id()))
+            .await
+            .map_err(|_| ())
+    }
+
+    /// Start detecting the dominance of a user's microphone producer, which has to be
+    /// available on this router. Only used on the primary router, so that a single
+    /// dominant speaker is picked across the whole room
+    pub async fn add_dominance_producer(
+        &self,
+        user_id: &str,
+        producer: &Producer,
+    ) -> Result<(), ()> {
+        self.track_speaker(user_id, producer);
+        self.active_speaker_observer
+            .add_producer(RtpObserverAddProducerOptions::new(producer.id()))
+            .await
+            .map_err(|_| ())
+    }
+
+    /// Map a producer to its user for the observers, until the producer is closed
+    fn track_speaker(&self, user_id: &str, producer: &Producer) {
+        let producer_id = producer.id();
+        self.speakers
+            .lock()
+            .unwrap()
+            .producers
+            .insert(producer_id, user_id.to_string());
+
+        let speakers = self.speakers.clone();
+        producer
+            .on_close(move || {
+                speakers.lock().unwrap().producers.remove(&producer_id);
+            })
+            .detach();
+    }
+}
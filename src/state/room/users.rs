@@ -24,6 +24,10 @@ impl<'r> RoomUsers {
     }
 
     pub async fn new(&'r self, id: String) -> Result<UserGuard<'r>, ApiError> {
+        if self.room.banned(&id) {
+            return Err(ApiError::UserBanned(id));
+        }
+
         let token = {
             let registrations = self.room.registrations.read().await;
             let mut rng = thread_rng();
@@ -84,6 +88,7 @@ impl<'r> RoomUsers {
         match users.remove(id) {
             Some(_) => {
                 debug!("Removed user {} from room {}", id, self.room.id());
+                self.room.clear_dominant_speaker(id);
                 self.room.send_event(RoomEvent::UserLeft(id.to_string()));
                 Ok(())
             }
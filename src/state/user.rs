@@ -1,12 +1,18 @@
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
 
+use mediasoup::data_producer::{DataProducer, DataProducerId};
 use mediasoup::producer::Producer;
+use mediasoup::router::Router;
 use mediasoup::rtp_parameters::MediaKind;
 
 use super::room::{Room, RoomEvent};
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ProduceType {
     #[serde(rename = "audio")]
     Audio,
@@ -28,6 +34,13 @@ impl ProduceType {
             ProduceType::Video | ProduceType::ScreenshareVideo => MediaKind::Video,
         }
     }
+
+    pub fn screenshare(self) -> bool {
+        matches!(
+            self,
+            ProduceType::ScreenshareAudio | ProduceType::ScreenshareVideo
+        )
+    }
 }
 
 impl From<ProduceType> for MediaKind {
@@ -50,8 +63,15 @@ pub struct User {
     id: String,
     token: Option<String>,
     room: Arc<Room>,
+    router: Option<Router>,
+
+    producers: HashMap<ProduceType, Producer>,
+    data_producers: HashMap<DataProducerId, DataProducer>,
 
-    audio: Option<Producer>,
+    server_muted: bool,
+    /// Audio producers paused by the server mute, resumed once it is lifted
+    server_paused: HashSet<ProduceType>,
+    server_deafened: bool,
 }
 
 impl User {
@@ -60,8 +80,14 @@ impl User {
             id: id,
             token: Some(token.clone()),
             room: room,
+            router: None,
 
-            audio: None,
+            producers: HashMap::new(),
+            data_producers: HashMap::new(),
+
+            server_muted: false,
+            server_paused: HashSet::new(),
+            server_deafened: false,
         }
     }
 
@@ -86,13 +112,21 @@ impl User {
         }
     }
 
+    /// Router the user's transports were created on
+    pub fn router(&self) -> Option<&Router> {
+        self.router.as_ref()
+    }
+
+    pub fn set_router(&mut self, router: Router) {
+        self.router = Some(router);
+    }
+
     pub fn get_producer(&self, produce_type: ProduceType) -> Option<&Producer> {
-        let producer = match produce_type {
-            ProduceType::Audio => &self.audio,
-            _ => todo!(),
-        };
+        self.producers.get(&produce_type)
+    }
 
-        producer.as_ref()
+    pub fn producers(&self) -> impl Iterator<Item = (&ProduceType, &Producer)> {
+        self.producers.iter()
     }
 
     pub fn set_producer(
@@ -103,15 +137,95 @@ impl User {
         if !self.registered() {
             return Err(());
         }
-        let producer = match produce_type {
-            ProduceType::Audio => &mut self.audio,
-            _ => todo!(),
+
+        self.server_paused.remove(&produce_type);
+        if let Some(producer) = &new_producer {
+            // Producers created while muted are created paused, see `produce_paused`
+            if producer.paused() && self.produce_paused(produce_type) {
+                self.server_paused.insert(produce_type);
+            }
+        }
+
+        match new_producer {
+            Some(producer) => self.producers.insert(produce_type, producer),
+            None => self.producers.remove(&produce_type),
         };
+        Ok(())
+    }
+
+    /// Whether a new producer of this type has to start paused because of a server mute
+    pub fn produce_paused(&self, produce_type: ProduceType) -> bool {
+        self.server_muted && produce_type.into_kind() == MediaKind::Audio
+    }
+
+    pub fn server_muted(&self) -> bool {
+        self.server_muted
+    }
+
+    /// Pause every audio producer of the user and keep them paused, or lift it
+    pub async fn set_server_muted(&mut self, muted: bool) {
+        if self.server_muted == muted {
+            return;
+        }
+
+        self.server_muted = muted;
+        if muted {
+            for (produce_type, producer) in &self.producers {
+                if produce_type.into_kind() == MediaKind::Audio && !producer.paused() {
+                    producer.pause().await.ok();
+                    self.server_paused.insert(*produce_type);
+                }
+            }
+        } else {
+            for produce_type in self.server_paused.drain() {
+                if let Some(producer) = self.producers.get(&produce_type) {
+                    producer.resume().await.ok();
+                }
+            }
+        }
+    }
+
+    /// Keep a producer paused once the server mute is lifted, used when the user pauses it themself
+    pub fn forget_server_pause(&mut self, produce_type: ProduceType) {
+        self.server_paused.remove(&produce_type);
+    }
+
+    pub fn server_deafened(&self) -> bool {
+        self.server_deafened
+    }
+
+    /// Only tracks the state, the consumers live in the user's connection and are paused there
+    pub fn set_server_deafened(&mut self, deafened: bool) {
+        self.server_deafened = deafened;
+    }
 
-        *producer = new_producer;
+    pub fn get_data_producer(&self, id: &DataProducerId) -> Option<&DataProducer> {
+        self.data_producers.get(id)
+    }
+
+    pub fn add_data_producer(&mut self, data_producer: DataProducer) -> Result<(), ()> {
+        if !self.registered() {
+            return Err(());
+        }
+
+        self.data_producers.insert(data_producer.id(), data_producer);
         Ok(())
     }
 
+    pub fn remove_data_producer(&mut self, id: &DataProducerId) -> Option<DataProducer> {
+        self.data_producers.remove(id)
+    }
+
+    /// Drop all producers, used when the underlying router is gone.
+    /// Returns the types of the dropped producers and the IDs of the dropped data producers
+    pub fn clear_producers(&mut self) -> (Vec<ProduceType>, Vec<DataProducerId>) {
+        self.server_paused.clear();
+        (
+            self.producers.drain().map(|(produce_type, _)| produce_type).collect(),
+            self.data_producers.drain().map(|(id, _)| id).collect(),
+        )
+    }
+
     pub fn into_info(&self) -> UserInfo {
         UserInfo::from(self)
     }
@@ -119,14 +233,35 @@ impl User {
 
 /// Structure passed to clients connected over WebSocket
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UserInfo {
     audio: bool,
+    video: bool,
+    screenshare_audio: bool,
+    screenshare_video: bool,
+    /// Producers the user paused without closing them
+    paused: Vec<ProduceType>,
+    data_producers: Vec<DataProducerId>,
+    server_muted: bool,
+    server_deafened: bool,
 }
 
 impl From<&User> for UserInfo {
     fn from(user: &User) -> UserInfo {
         UserInfo {
-            audio: user.audio.is_some(),
+            audio: user.producers.contains_key(&ProduceType::Audio),
+            video: user.producers.contains_key(&ProduceType::Video),
+            screenshare_audio: user.producers.contains_key(&ProduceType::ScreenshareAudio),
+            screenshare_video: user.producers.contains_key(&ProduceType::ScreenshareVideo),
+            paused: user
+                .producers
+                .iter()
+                .filter(|(_, producer)| producer.paused())
+                .map(|(produce_type, _)| *produce_type)
+                .collect(),
+            data_producers: user.data_producers.keys().copied().collect(),
+            server_muted: user.server_muted,
+            server_deafened: user.server_deafened,
         }
     }
 }
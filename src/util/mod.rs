@@ -1 +1,2 @@
+pub mod ogg;
 pub mod variables;
@@ -0,0 +1,231 @@
+use std::io;
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+const HEADER_BOS: u8 = 0x02;
+const HEADER_EOS: u8 = 0x04;
+/// Samples the decoder discards at the start of the stream, the value recommended by RFC 7845
+const PRE_SKIP: u16 = 312;
+
+/// Minimal Ogg/Opus file writer (RFC 7845), writing one Opus packet per page
+pub struct OggOpusWriter {
+    file: BufWriter<File>,
+    serial: u32,
+    sequence: u32,
+    /// Last packet and the granule position at its end, held back so it can be flagged as end of stream
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl OggOpusWriter {
+    pub async fn create(path: &Path, channels: u8) -> io::Result<Self> {
+        let mut writer = OggOpusWriter {
+            file: BufWriter::new(File::create(path).await?),
+            serial: rand::random(),
+            sequence: 0,
+            pending: None,
+        };
+
+        writer
+            .write_page(&opus_head(channels), 0, HEADER_BOS)
+            .await?;
+        writer.write_page(&opus_tags(), 0, 0).await?;
+
+        Ok(writer)
+    }
+
+    /// Write an Opus packet, `granule` is the sample position at the end of the packet
+    pub async fn write_packet(&mut self, packet: &[u8], granule: u64) -> io::Result<()> {
+        if let Some((pending, pending_granule)) = self.pending.take() {
+            self.write_page(&pending, pending_granule, 0).await?;
+        }
+
+        // Granule positions include the samples skipped by the decoder
+        self.pending = Some((packet.to_vec(), granule + PRE_SKIP as u64));
+        Ok(())
+    }
+
+    /// Write the last packet flagged as end of stream and flush the file
+    pub async fn finish(&mut self) -> io::Result<()> {
+        if let Some((pending, pending_granule)) = self.pending.take() {
+            self.write_page(&pending, pending_granule, HEADER_EOS)
+                .await?;
+        }
+
+        self.file.flush().await
+    }
+
+    async fn write_page(&mut self, packet: &[u8], granule: u64, header_type: u8) -> io::Result<()> {
+        let page = ogg_page(packet, granule, self.serial, self.sequence, header_type);
+        self.sequence += 1;
+        self.file.write_all(&page).await
+    }
+}
+
+/// Identification header, the first packet of the stream
+fn opus_head(channels: u8) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // Version
+    head.push(channels);
+    head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&48000u32.to_le_bytes()); // Original sample rate
+    head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+    head.push(0); // Channel mapping family
+    head
+}
+
+/// Comment header, the second packet of the stream
+fn opus_tags() -> Vec<u8> {
+    let vendor = concat!("Vortex ", env!("CARGO_PKG_VERSION"));
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&0u32.to_le_bytes()); // No user comments
+    tags
+}
+
+/// Ogg page holding a single packet
+fn ogg_page(packet: &[u8], granule: u64, serial: u32, sequence: u32, header_type: u8) -> Vec<u8> {
+    let mut page = Vec::with_capacity(27 + packet.len() / 255 + 1 + packet.len());
+    page.extend_from_slice(b"OggS");
+    page.push(0); // Version
+    page.push(header_type);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]); // Checksum, filled in below
+
+    // Lacing values, a packet is split into 255 byte segments ended by a shorter one
+    let segments = packet.len() / 255 + 1;
+    page.push(segments as u8);
+    page.extend(std::iter::repeat(255).take(segments - 1));
+    page.push((packet.len() % 255) as u8);
+    page.extend_from_slice(packet);
+
+    let checksum = crc32(&page);
+    page[22..26].copy_from_slice(&checksum.to_le_bytes());
+    page
+}
+
+/// Number of 48kHz samples in an Opus packet, taken from its TOC byte (RFC 6716 section 3.1)
+pub fn opus_packet_samples(packet: &[u8]) -> u64 {
+    let toc = match packet.first() {
+        Some(toc) => *toc,
+        None => return 0,
+    };
+
+    let config = toc >> 3;
+    let frame_size: u64 = match config {
+        // SILK-only
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        // Hybrid
+        12..=15 => [480, 960][(config % 2) as usize],
+        // CELT-only
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    };
+
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| (count & 0x3f) as u64),
+    };
+
+    frame_size * frames
+}
+
+/// CRC-32 as used by Ogg, polynomial 0x04c11db7 without reflection
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_ogg_checksum() {
+        assert_eq!(crc32(b""), 0);
+        // CRC-32/POSIX without the final xor, as specified by Ogg
+        assert_eq!(crc32(b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn crc32_of_page_with_checksum_field_zeroed() {
+        let page = ogg_page(b"abc", 960, 1, 2, 0);
+        let mut zeroed = page.clone();
+        zeroed[22..26].copy_from_slice(&[0; 4]);
+
+        assert_eq!(page[22..26], crc32(&zeroed).to_le_bytes());
+    }
+
+    #[test]
+    fn page_header_layout() {
+        let page = ogg_page(&[7; 10], 0x0102_0304_0506_0708, 0xdead_beef, 5, HEADER_EOS);
+
+        assert_eq!(&page[0..4], b"OggS");
+        assert_eq!(page[4], 0);
+        assert_eq!(page[5], HEADER_EOS);
+        assert_eq!(page[6..14], 0x0102_0304_0506_0708u64.to_le_bytes());
+        assert_eq!(page[14..18], 0xdead_beefu32.to_le_bytes());
+        assert_eq!(page[18..22], 5u32.to_le_bytes());
+        assert_eq!(page[26], 1);
+        assert_eq!(page[27], 10);
+        assert_eq!(&page[28..], &[7; 10]);
+    }
+
+    #[test]
+    fn page_lacing_values() {
+        let page = ogg_page(&[0; 600], 0, 0, 0, 0);
+        assert_eq!(page[26], 3);
+        assert_eq!(&page[27..30], &[255, 255, 90]);
+        assert_eq!(page.len(), 30 + 600);
+
+        // A packet that is a multiple of 255 bytes ends with an empty segment
+        let page = ogg_page(&[0; 255], 0, 0, 0, 0);
+        assert_eq!(page[26], 2);
+        assert_eq!(&page[27..29], &[255, 0]);
+    }
+
+    #[test]
+    fn opus_head_layout() {
+        let head = opus_head(2);
+
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[0..8], b"OpusHead");
+        assert_eq!(head[8], 1);
+        assert_eq!(head[9], 2);
+        assert_eq!(head[10..12], 312u16.to_le_bytes());
+        assert_eq!(head[12..16], 48000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn opus_packet_samples_from_toc() {
+        assert_eq!(opus_packet_samples(&[]), 0);
+        // SILK-only 20ms, one frame
+        assert_eq!(opus_packet_samples(&[1 << 3]), 960);
+        // SILK-only 60ms, two frames
+        assert_eq!(opus_packet_samples(&[(3 << 3) | 1]), 5760);
+        // Hybrid 10ms, one frame
+        assert_eq!(opus_packet_samples(&[12 << 3]), 480);
+        // CELT-only 2.5ms, two frames of different size
+        assert_eq!(opus_packet_samples(&[(16 << 3) | 2]), 240);
+        // CELT-only 20ms, arbitrary number of frames given in the second byte
+        assert_eq!(opus_packet_samples(&[(31 << 3) | 3, 0x80 | 3]), 2880);
+        assert_eq!(opus_packet_samples(&[(31 << 3) | 3]), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::env;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
@@ -5,6 +6,8 @@ use std::str::FromStr;
 use mediasoup::data_structures::{ListenInfo, Protocol};
 use mediasoup::webrtc_transport::WebRtcTransportListenInfos;
 
+use crate::rtc::types::RtpEncryption;
+
 lazy_static! {
     // HTTP API
     pub static ref HTTP_HOST: SocketAddr = env::var("HTTP_HOST")
@@ -17,7 +20,8 @@ lazy_static! {
         env::var("MANAGE_TOKEN").expect("Missing MANAGE_TOKEN environment variable.");
 
     // RTC
-    pub static ref RTC_IPS: WebRtcTransportListenInfos = {
+    /// UDP listen info for every configured IP, in the order they were given
+    pub static ref RTC_IPS: Vec<ListenInfo> = {
         let ip_list = env::var("RTC_IPS").expect("Missing RTC_IPS environment variable.");
         let ip_list = ip_list.split(';');
         let mut ip_vec = Vec::new();
@@ -48,10 +52,27 @@ lazy_static! {
             }
         }
 
-        let listen_infos = WebRtcTransportListenInfos::new(ip_vec.remove(0));
-        listen_infos
+        ip_vec
+    };
+    /// UDP and TCP listen infos for every configured IP, used for WebRTC transports
+    pub static ref RTC_LISTEN_INFOS: WebRtcTransportListenInfos = {
+        let mut listen_infos = RTC_IPS.iter().flat_map(|udp| {
+            let tcp = ListenInfo {
+                protocol: Protocol::Tcp,
+                ..*udp
+            };
+            vec![*udp, tcp]
+        });
+
+        let first = listen_infos.next().expect("RTC_IPS must contain at least one IP");
+        listen_infos.fold(WebRtcTransportListenInfos::new(first), |infos, info| infos.insert(info))
     };
 
+    /// Run a WebRTC server on this port (plus the worker index) for each worker instead of
+    /// giving every WebRTC transport its own port from the RTC port range
+    pub static ref RTC_SERVER_PORT: Option<u16> = env::var("RTC_SERVER_PORT")
+        .ok()
+        .map(|port| port.parse().expect("RTC_SERVER_PORT is not a valid 16-bit number"));
     pub static ref RTC_MIN_PORT: u16 = env::var("RTC_MIN_PORT")
         .unwrap_or_else(|_| "10000".to_string())
         .parse()
@@ -60,12 +81,51 @@ lazy_static! {
         .unwrap_or_else(|_| "11000".to_string())
         .parse()
         .expect("RTC_MAX_PORT is not a valid 16-bit number");
+    pub static ref RTC_WORKERS: usize = env::var("RTC_WORKERS")
+        .ok()
+        .map(|workers| workers.parse().expect("RTC_WORKERS is not a valid number"))
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    /// Encryption allowed for RTP clients, in the order of preference
+    pub static ref RTC_SRTP_CRYPTO_SUITES: Vec<RtpEncryption> =
+        env::var("RTC_SRTP_CRYPTO_SUITES")
+            .unwrap_or_else(|_| {
+                "AEAD_AES_256_GCM,AEAD_AES_128_GCM,AES_CM_128_HMAC_SHA1_80,AES_CM_128_HMAC_SHA1_32"
+                    .to_string()
+            })
+            .split(',')
+            .map(|suite| suite.trim().parse().expect("Not a valid SRTP crypto suite"))
+            .collect();
+    pub static ref RECORDING_DIR: String =
+        env::var("RECORDING_DIR").unwrap_or_else(|_| "recordings".to_string());
     pub static ref DISABLE_RTP: bool = env::var("DISABLE_RTP").map_or(false, |v| v == "1");
+    pub static ref CLOSE_ROOMS_ON_CRASH: bool =
+        env::var("CLOSE_ROOMS_ON_CRASH").map_or(false, |v| v == "1");
 }
 
 pub fn preflight_checks() {
     format!("{}", *WS_URL);
     format!("{}", *MANAGE_TOKEN);
 
-    format!("{}", RTC_IPS.len());
+    format!("{}", RTC_LISTEN_INFOS.len());
+    format!("{}", RTC_SRTP_CRYPTO_SUITES.len());
+    if *RTC_WORKERS == 0 {
+        panic!("RTC_WORKERS must be at least 1");
+    }
+    if *RTC_MIN_PORT > *RTC_MAX_PORT {
+        panic!("RTC_MIN_PORT must not be greater than RTC_MAX_PORT");
+    }
+
+    if let Some(port) = *RTC_SERVER_PORT {
+        // Every worker listens on its own port, counting up from RTC_SERVER_PORT
+        let last_port = u16::try_from(*RTC_WORKERS - 1)
+            .ok()
+            .and_then(|workers| port.checked_add(workers))
+            .expect("RTC_SERVER_PORT + RTC_WORKERS exceeds the highest port number");
+        if port <= *RTC_MAX_PORT && last_port >= *RTC_MIN_PORT {
+            panic!(
+                "Ports {}-{} used by the WebRTC servers overlap with RTC_MIN_PORT-RTC_MAX_PORT",
+                port, last_port
+            );
+        }
+    }
 }
@@ -36,12 +36,25 @@ pub enum WSErrorType {
     UserNotFound(String),
 
     TransportConnectionFailure,
+    IceRestartFailure,
+    StatsFailure,
 
     ProducerFailure,
     ProducerNotFound,
+    VideoNotAllowed,
+    ScreenshareNotAllowed,
+    ServerMuted,
 
     ConsumerFailure,
     ConsumerNotFound(String),
+    ConsumerRequestFailure,
+    ServerDeafened,
+
+    DataProducerFailure,
+    DataProducerNotFound(String),
+
+    DataConsumerFailure,
+    DataConsumerNotFound(String),
 }
 
 impl WSErrorType {
@@ -58,18 +71,47 @@ impl Display for WSErrorType {
             WSErrorType::TransportConnectionFailure => {
                 write!(f, "An error occured while trying to connect transport")
             }
+            WSErrorType::IceRestartFailure => {
+                write!(f, "An error occured while trying to restart ICE")
+            }
+            WSErrorType::StatsFailure => write!(f, "An error occured while collecting stats"),
 
             WSErrorType::ProducerFailure => write!(
                 f,
                 "An unknown error occured while setting up an RTC producer"
             ),
             WSErrorType::ProducerNotFound => write!(f, "Producer doesn't exist"),
+            WSErrorType::VideoNotAllowed => write!(f, "Video is not allowed in this room"),
+            WSErrorType::ScreenshareNotAllowed => {
+                write!(f, "You are not allowed to share your screen in this room")
+            }
+            WSErrorType::ServerMuted => write!(f, "You have been muted by a moderator"),
 
             WSErrorType::ConsumerFailure => write!(
                 f,
                 "An unknown error occured while setting up an RTC consumer"
             ),
             WSErrorType::ConsumerNotFound(id) => write!(f, "Consumer with ID {} doesn't exist", id),
+            WSErrorType::ConsumerRequestFailure => {
+                write!(f, "An error occured while handling the consumer request")
+            }
+            WSErrorType::ServerDeafened => write!(f, "You have been deafened by a moderator"),
+
+            WSErrorType::DataProducerFailure => write!(
+                f,
+                "An unknown error occured while setting up a data producer"
+            ),
+            WSErrorType::DataProducerNotFound(id) => {
+                write!(f, "Data producer with ID {} doesn't exist", id)
+            }
+
+            WSErrorType::DataConsumerFailure => write!(
+                f,
+                "An unknown error occured while setting up a data consumer"
+            ),
+            WSErrorType::DataConsumerNotFound(id) => {
+                write!(f, "Data consumer with ID {} doesn't exist", id)
+            }
         }
     }
 }
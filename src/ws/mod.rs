@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use futures::{
@@ -6,14 +7,20 @@ use futures::{
     SinkExt, StreamExt,
 };
 
+use mediasoup::consumer::ConsumerLayers;
+use mediasoup::producer::Producer;
+use tokio::sync::broadcast::error::RecvError;
 use warp::ws::{Message, WebSocket, Ws};
 use warp::{Filter, Rejection, Reply};
 
 use crate::{
-    rtc::RtcState,
+    rtc::{
+        types::{AutoSubscribe, InitializationInput},
+        ConsumerEvent, ConsumerRequestError, RtcState,
+    },
     state::{
         room::{Room, RoomEvent},
-        user::UserInfo,
+        user::{ProduceType, UserInfo},
     },
 };
 
@@ -24,12 +31,16 @@ use error::{WSCloseType, WSErrorType};
 use types::{WSCommand, WSCommandType, WSEvent, WSReplyType};
 
 pub fn route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Copy {
-    warp::ws::ws().map(|ws: Ws| ws.on_upgrade(on_connection))
+    warp::ws::ws()
+        .and(warp::addr::remote())
+        .map(|ws: Ws, remote: Option<SocketAddr>| {
+            ws.on_upgrade(move |ws| on_connection(ws, remote.map(|remote| remote.ip())))
+        })
 }
 
-async fn on_connection(ws: WebSocket) {
+async fn on_connection(ws: WebSocket, remote: Option<IpAddr>) {
     let (mut ws_sink, mut ws_stream) = ws.split();
-    let result = handle(&mut ws_sink, &mut ws_stream).await;
+    let result = handle(&mut ws_sink, &mut ws_stream, remote).await;
     if let Err(close) = result {
         let code = close as u16;
         let reason = close.to_string();
@@ -42,6 +53,7 @@ async fn on_connection(ws: WebSocket) {
 async fn handle(
     ws_sink: &mut SplitSink<WebSocket, Message>,
     ws_stream: &mut SplitStream<WebSocket>,
+    remote: Option<IpAddr>,
 ) -> Result<(), WSCloseType> {
     // Authentication
     let (room, user_id) = loop {
@@ -68,10 +80,8 @@ async fn handle(
                                     user_id: id.clone(),
                                     room_id: room.id().to_string(),
                                     rtp_capabilities: room
-                                        .router()
-                                        .ok_or(WSCloseType::RoomClosed)?
                                         .rtp_capabilities()
-                                        .clone(),
+                                        .ok_or(WSCloseType::RoomClosed)?,
                                 }
                                 .to_message(out.id)?,
                             )
@@ -99,10 +109,8 @@ async fn handle(
                     match out.command_type {
                         WSCommandType::Ping => {}
                         WSCommandType::InitializeTransports { init_data } => {
-                            let router = room.router().ok_or(WSCloseType::RoomClosed)?;
-                            let rtc_state = RtcState::initialize(router, init_data)
-                                .await
-                                .map_err(|_| WSCloseType::ServerError)?;
+                            let rtc_state =
+                                initialize_transports(&room, &user_id, init_data, remote).await?;
                             let reply_data = rtc_state.get_init_data();
 
                             ws_sink
@@ -129,7 +137,7 @@ async fn handle(
     // TODO: implement some sort of way to automatically remove a user from a room if the thread panics
     // the Room user remove function is async but the Drop trait is not
 
-    let result = event_loop(&room, &user_id, rtc_state, ws_sink, ws_stream).await;
+    let result = event_loop(&room, &user_id, remote, rtc_state, ws_sink, ws_stream).await;
     room.users().remove(&user_id).await.ok();
     result
 }
@@ -137,12 +145,18 @@ async fn handle(
 async fn event_loop(
     room: &Arc<Room>,
     user_id: &str,
+    remote: Option<IpAddr>,
     mut rtc_state: RtcState,
     ws_sink: &mut SplitSink<WebSocket, Message>,
     ws_stream: &mut SplitStream<WebSocket>,
 ) -> Result<(), WSCloseType> {
     let mut room_stream = room.subscribe().ok_or(WSCloseType::RoomClosed)?;
     let mut ws_stream = ws_stream.fuse();
+    // Set when the room's router was recreated and the client has to set up new transports
+    let mut reinitialize = false;
+
+    // Subscribed to room events first so no producer started in between is missed
+    auto_consume_all(room, user_id, &mut rtc_state, ws_sink).await?;
 
     loop {
         tokio::select! {
@@ -166,7 +180,59 @@ async fn event_loop(
                                     ).await?;
                                 }
                             },
+                            WSCommandType::RestartIce { transport_id } => {
+                                match rtc_state.restart_ice(*transport_id).await {
+                                    Ok(ice_parameters) => ws_sink.send(
+                                        WSReplyType::RestartIce { ice_parameters }.to_message(out.id)?
+                                    ).await?,
+                                    Err(_) => ws_sink.send(
+                                        WSErrorType::IceRestartFailure.to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::InitializeTransports { init_data } if reinitialize => {
+                                rtc_state = initialize_transports(room, user_id, init_data.clone(), remote).await?;
+                                reinitialize = false;
+
+                                let reply_data = rtc_state.get_init_data();
+                                ws_sink.send(
+                                    WSReplyType::InitializeTransports { reply_data }.to_message(out.id)?
+                                ).await?;
+                                auto_consume_all(room, user_id, &mut rtc_state, ws_sink).await?;
+                            },
                             WSCommandType::RoomInfo => room_info(out, room, ws_sink).await?,
+                            WSCommandType::GetStats => {
+                                let producers: Vec<(ProduceType, Producer)> = {
+                                    let users = room.users();
+                                    let user = users
+                                        .get(user_id)
+                                        .await
+                                        .ok_or_else(|| WSCloseType::ServerError)?;
+                                    let user = user.read().await;
+                                    user.producers()
+                                        .map(|(produce_type, producer)| (*produce_type, producer.clone()))
+                                        .collect()
+                                };
+
+                                match rtc_state.get_stats(producers).await {
+                                    Ok(stats) => ws_sink.send(
+                                        WSReplyType::GetStats { stats }.to_message(out.id)?
+                                    ).await?,
+                                    Err(_) => ws_sink.send(
+                                        WSErrorType::StatsFailure.to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::StartProduce { produce_type, .. } if *produce_type == ProduceType::Video && !room.video_allowed() => {
+                                ws_sink.send(
+                                    WSErrorType::VideoNotAllowed.to_message(out)?
+                                ).await?;
+                            },
+                            WSCommandType::StartProduce { produce_type, .. } if produce_type.screenshare() && !room.can_screenshare(user_id) => {
+                                ws_sink.send(
+                                    WSErrorType::ScreenshareNotAllowed.to_message(out)?
+                                ).await?;
+                            },
                             WSCommandType::StartProduce { produce_type, rtp_parameters } => {
                                 let users = room.users();
                                 let user = users
@@ -174,13 +240,24 @@ async fn event_loop(
                                     .await
                                     .ok_or_else(|| WSCloseType::ServerError)?;
 
-                                let result = rtc_state.start_produce(produce_type, rtp_parameters.clone()).await.ok();
+                                let paused = user.read().await.produce_paused(*produce_type);
+                                let result = rtc_state.start_produce(produce_type, rtp_parameters.clone(), paused).await.ok();
 
                                 match result {
                                     Some(producer) => {
                                         let producer_id = producer.id();
                                         let mut mut_user = user.write().await;
-                                        mut_user.set_producer(*produce_type, Some(producer)).ok();
+                                        mut_user.set_producer(*produce_type, Some(producer.clone())).ok();
+                                        drop(mut_user);
+                                        // Recordings and egresses lock the room's users, so release ours first
+                                        drop(user);
+
+                                        if *produce_type == ProduceType::Audio {
+                                            room.add_audio_producer(rtc_state.router(), user_id, &producer).await.ok();
+                                            room.record_producer(rtc_state.router(), user_id, &producer).await;
+                                        }
+                                        room.egress_producer(rtc_state.router(), user_id, *produce_type, &producer).await;
+
                                         room.send_event(RoomEvent::UserStartProduce(user_id.to_string(), *produce_type));
 
                                         ws_sink.send(
@@ -216,18 +293,71 @@ async fn event_loop(
                                     ).await?,
                                 }
                             },
+                            WSCommandType::PauseProduce { produce_type }
+                            | WSCommandType::ResumeProduce { produce_type } => {
+                                let paused = matches!(out.command_type, WSCommandType::PauseProduce { .. });
+                                let users = room.users();
+                                let user = users
+                                    .get(user_id)
+                                    .await
+                                    .ok_or_else(|| WSCloseType::ServerError)?;
+
+                                let mut user = user.write().await;
+                                if !paused && user.produce_paused(*produce_type) {
+                                    ws_sink.send(
+                                        WSErrorType::ServerMuted.to_message(out)?
+                                    ).await?;
+                                } else {
+                                    if paused {
+                                        user.forget_server_pause(*produce_type);
+                                    }
+
+                                    match user.get_producer(*produce_type) {
+                                        Some(producer) => {
+                                            // The request error is not Send, so only keep whether it worked
+                                            let success = match paused {
+                                                true => producer.pause().await.is_ok(),
+                                                false => producer.resume().await.is_ok(),
+                                            };
+
+                                            if success {
+                                                let (event, reply) = match paused {
+                                                    true => (
+                                                        RoomEvent::UserProducerPaused(user_id.to_string(), *produce_type),
+                                                        WSReplyType::PauseProduce,
+                                                    ),
+                                                    false => (
+                                                        RoomEvent::UserProducerResumed(user_id.to_string(), *produce_type),
+                                                        WSReplyType::ResumeProduce,
+                                                    ),
+                                                };
+                                                room.send_event(event);
+                                                ws_sink.send(reply.to_message(out.id)?).await?;
+                                            } else {
+                                                ws_sink.send(
+                                                    WSErrorType::ProducerFailure.to_message(out)?
+                                                ).await?;
+                                            }
+                                        },
+                                        None => ws_sink.send(
+                                            WSErrorType::ProducerNotFound.to_message(out)?
+                                        ).await?,
+                                    }
+                                }
+                            },
                             WSCommandType::StartConsume { produce_type, user_id: producing_id } => {
                                 let producing_id = producing_id.clone();
+                                sync_deafened(room, user_id, &mut rtc_state).await;
                                 let users = room.users();
                                 match users.get(&producing_id).await {
                                     Some(producing_user) => {
                                         let producing_user = producing_user.read().await;
                                         match producing_user.get_producer(*produce_type) {
                                             Some(producer) => {
-                                                let router = room.router().ok_or_else(|| WSCloseType::ServerError)?;
+                                                let router = producing_user.router().ok_or_else(|| WSCloseType::ServerError)?;
 
-                                                if router.can_consume(&producer.id(), rtc_state.rtp_capabilities()) {
-                                                    match rtc_state.start_consume(producer.id()).await.ok() {
+                                                if rtc_state.can_consume(router, producer) {
+                                                    match rtc_state.start_consume(room, router, &producing_id, *produce_type, producer.id(), true).await.ok() {
                                                         Some(consumer) => ws_sink.send(
                                                             WSReplyType::StartConsume {
                                                                 id: consumer.id(),
@@ -270,6 +400,11 @@ async fn event_loop(
                                     ).await?,
                                 }
                             },
+                            WSCommandType::SetConsumerPause { paused: false, .. } if rtc_state.deafened() => {
+                                ws_sink.send(
+                                    WSErrorType::ServerDeafened.to_message(out)?
+                                ).await?;
+                            },
                             WSCommandType::SetConsumerPause { id, paused } => {
                                 let id = id.clone();
                                 match rtc_state.set_consumer_pause(&id, *paused).await {
@@ -281,6 +416,155 @@ async fn event_loop(
                                     ).await?,
                                 }
                             },
+                            WSCommandType::SetConsumerPriority { id, priority } => {
+                                let id = id.clone();
+                                match rtc_state.set_consumer_priority(&id, *priority).await {
+                                    Ok(_) => ws_sink.send(
+                                        WSReplyType::SetConsumerPriority.to_message(out.id)?
+                                    ).await?,
+                                    Err(_) => ws_sink.send(
+                                        WSErrorType::ConsumerNotFound(id.to_string()).to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::RequestKeyFrame { id } => {
+                                let id = id.clone();
+                                match rtc_state.request_key_frame(&id).await {
+                                    Ok(_) => ws_sink.send(
+                                        WSReplyType::RequestKeyFrame.to_message(out.id)?
+                                    ).await?,
+                                    Err(ConsumerRequestError::NotFound) => ws_sink.send(
+                                        WSErrorType::ConsumerNotFound(id.to_string()).to_message(out)?
+                                    ).await?,
+                                    Err(ConsumerRequestError::Failed) => ws_sink.send(
+                                        WSErrorType::ConsumerRequestFailure.to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::SetPreferredLayers { id, spatial_layer, temporal_layer } => {
+                                let id = id.clone();
+                                let layers = ConsumerLayers {
+                                    spatial_layer: *spatial_layer,
+                                    temporal_layer: *temporal_layer,
+                                };
+                                match rtc_state.set_preferred_layers(&id, layers).await {
+                                    Ok(_) => ws_sink.send(
+                                        WSReplyType::SetPreferredLayers.to_message(out.id)?
+                                    ).await?,
+                                    Err(ConsumerRequestError::NotFound) => ws_sink.send(
+                                        WSErrorType::ConsumerNotFound(id.to_string()).to_message(out)?
+                                    ).await?,
+                                    Err(ConsumerRequestError::Failed) => ws_sink.send(
+                                        WSErrorType::ConsumerRequestFailure.to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::StartDataProduce { sctp_stream_parameters, label, protocol } => {
+                                let users = room.users();
+                                let user = users
+                                    .get(user_id)
+                                    .await
+                                    .ok_or_else(|| WSCloseType::ServerError)?;
+
+                                let result = rtc_state.start_data_produce(
+                                    sctp_stream_parameters.clone(),
+                                    label.clone(),
+                                    protocol.clone(),
+                                ).await.ok();
+
+                                match result {
+                                    Some(data_producer) => {
+                                        let data_producer_id = data_producer.id();
+                                        user.write().await.add_data_producer(data_producer).ok();
+                                        room.send_event(RoomEvent::UserStartDataProduce(
+                                            user_id.to_string(),
+                                            data_producer_id,
+                                            label.clone(),
+                                        ));
+
+                                        ws_sink.send(
+                                            WSReplyType::StartDataProduce { data_producer_id }.to_message(out.id)?
+                                        ).await?;
+                                    },
+                                    None => {
+                                        error!("Error while trying to start data produce for user {}", user_id);
+                                        ws_sink.send(
+                                            WSErrorType::DataProducerFailure.to_message(out)?
+                                        ).await?;
+                                    }
+                                }
+                            },
+                            WSCommandType::StopDataProduce { id } => {
+                                let id = *id;
+                                let users = room.users();
+                                let user = users
+                                    .get(user_id)
+                                    .await
+                                    .ok_or_else(|| WSCloseType::ServerError)?;
+
+                                let removed = user.write().await.remove_data_producer(&id);
+                                match removed {
+                                    Some(_) => {
+                                        room.send_event(RoomEvent::UserStopDataProduce(user_id.to_string(), id));
+                                        ws_sink.send(
+                                            WSReplyType::StopDataProduce.to_message(out.id)?
+                                        ).await?;
+                                    },
+                                    None => ws_sink.send(
+                                        WSErrorType::DataProducerNotFound(id.to_string()).to_message(out)?
+                                    ).await?,
+                                }
+                            },
+                            WSCommandType::StartDataConsume { user_id: producing_id, data_producer_id } => {
+                                let producing_id = producing_id.clone();
+                                let data_producer_id = *data_producer_id;
+                                let users = room.users();
+                                match users.get(&producing_id).await {
+                                    Some(producing_user) => {
+                                        let producing_user = producing_user.read().await;
+                                        match producing_user.get_data_producer(&data_producer_id) {
+                                            Some(_) => {
+                                                let router = producing_user.router().ok_or_else(|| WSCloseType::ServerError)?;
+
+                                                match rtc_state.start_data_consume(room, router, data_producer_id).await.ok() {
+                                                    Some(data_consumer) => ws_sink.send(
+                                                        WSReplyType::StartDataConsume {
+                                                            id: data_consumer.id(),
+                                                            data_producer_id: data_consumer.data_producer_id(),
+                                                            sctp_stream_parameters: data_consumer.sctp_stream_parameters(),
+                                                            label: data_consumer.label().clone(),
+                                                            protocol: data_consumer.protocol().clone(),
+                                                        }.to_message(out.id)?
+                                                    ).await?,
+                                                    None => {
+                                                        error!("Error while trying to start data consume");
+                                                        ws_sink.send(
+                                                            WSErrorType::DataConsumerFailure.to_message(out)?
+                                                        ).await?;
+                                                    }
+                                                };
+                                            },
+                                            None => ws_sink.send(
+                                                WSErrorType::DataProducerNotFound(data_producer_id.to_string()).to_message(out)?
+                                            ).await?,
+                                        }
+                                    },
+                                    None => ws_sink.send(
+                                        WSErrorType::UserNotFound(producing_id).to_message(out)?
+                                    ).await?,
+                                };
+                            },
+                            WSCommandType::StopDataConsume { id } => {
+                                let id = *id;
+                                match rtc_state.stop_data_consume(&id) {
+                                    Ok(_) => ws_sink.send(
+                                        WSReplyType::StopDataConsume.to_message(out.id)?
+                                    ).await?,
+                                    Err(_) => ws_sink.send(
+                                        WSErrorType::DataConsumerNotFound(id.to_string()).to_message(out)?
+                                    ).await?,
+                                }
+                            },
                             _ => return Err(WSCloseType::InvalidState),
                         };
                     }
@@ -288,8 +572,43 @@ async fn event_loop(
                     return Ok(());
                 }
             },
+            Some(event) = rtc_state.consumer_event() => {
+                match event {
+                    ConsumerEvent::LayersChanged(id, layers) => {
+                        let event = WSEvent::ConsumerLayersChanged { id, layers };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    ConsumerEvent::Closed(id, reason) => {
+                        // The client may have stopped the consumer itself in the meantime
+                        if rtc_state.stop_consume(&id).is_ok() {
+                            let event = WSEvent::ConsumerClosed { id, reason };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
+                }
+            },
             event = room_stream.recv() => {
-                let event = event.map_err(|_| WSCloseType::ServerError)?;
+                let event = match event {
+                    Ok(event) => event,
+                    // Mostly volume reports pile up, which are not worth dropping a slow client for
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("User {} missed {} room events", user_id, skipped);
+
+                        // The dominant speaker is state rather than a one-off, so resync it
+                        rtc_state.refresh_priorities(room).await;
+                        let event = WSEvent::DominantSpeaker { id: room.dominant_speaker() };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                        continue;
+                    },
+                    Err(RecvError::Closed) => return Err(WSCloseType::ServerError),
+                };
+
                 match event {
                     RoomEvent::UserJoined(id) => {
                         if id != user_id {
@@ -299,6 +618,16 @@ async fn event_loop(
                                 .await?;
                         }
                     },
+                    RoomEvent::UserKicked(id, reason) => {
+                        // Everyone else is told through UserLeft
+                        if id == user_id {
+                            let event = WSEvent::Kicked { reason };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                            return Err(WSCloseType::Kicked);
+                        }
+                    },
                     RoomEvent::UserLeft(id) => {
                         if id == user_id {
                             return Err(WSCloseType::Kicked);
@@ -311,10 +640,14 @@ async fn event_loop(
                     },
                     RoomEvent::UserStartProduce(id, produce_type) => {
                         if id != user_id {
-                            let event = WSEvent::UserStartProduce { id, produce_type };
+                            let event = WSEvent::UserStartProduce { id: id.clone(), produce_type };
                             ws_sink
                                 .send(Message::text(serde_json::to_string(&event)?))
                                 .await?;
+
+                            if !reinitialize {
+                                auto_consume(room, user_id, &id, produce_type, &mut rtc_state, ws_sink).await?;
+                            }
                         }
                     },
                     RoomEvent::UserStopProduce(id, produce_type) => {
@@ -325,6 +658,97 @@ async fn event_loop(
                                 .await?;
                         }
                     }
+                    RoomEvent::UserProducerPaused(id, produce_type) => {
+                        if id != user_id {
+                            let event = WSEvent::UserProducerPaused { id, produce_type };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
+                    RoomEvent::UserProducerResumed(id, produce_type) => {
+                        if id != user_id {
+                            let event = WSEvent::UserProducerResumed { id, produce_type };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
+                    RoomEvent::UserServerMuted(id, muted) => {
+                        let event = WSEvent::UserServerMuted { id, muted };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::UserServerDeafened(id, deafened) => {
+                        if id == user_id {
+                            rtc_state.set_deafened(deafened).await;
+                        }
+
+                        let event = WSEvent::UserServerDeafened { id, deafened };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::UserStartDataProduce(id, data_producer_id, label) => {
+                        if id != user_id {
+                            let event = WSEvent::UserStartDataProduce { id, data_producer_id, label };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
+                    RoomEvent::UserStopDataProduce(id, data_producer_id) => {
+                        if id != user_id {
+                            let event = WSEvent::UserStopDataProduce { id, data_producer_id };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
+                    RoomEvent::UserSpeaking(id, volume) => {
+                        let event = WSEvent::UserSpeaking { id, volume };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::UserSilent(id) => {
+                        let event = WSEvent::UserSilent { id };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::DominantSpeaker(id) => {
+                        rtc_state.refresh_priorities(room).await;
+
+                        let event = WSEvent::DominantSpeaker { id };
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::RecordingStarted => {
+                        let event = WSEvent::RecordingStarted;
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::RecordingStopped => {
+                        let event = WSEvent::RecordingStopped;
+                        ws_sink
+                            .send(Message::text(serde_json::to_string(&event)?))
+                            .await?;
+                    },
+                    RoomEvent::RouterRecreated(user_ids) => {
+                        if user_ids.contains(user_id) {
+                            let rtp_capabilities = room.rtp_capabilities().ok_or(WSCloseType::RoomClosed)?;
+                            reinitialize = true;
+
+                            let event = WSEvent::RouterRecreated { rtp_capabilities };
+                            ws_sink
+                                .send(Message::text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                    },
                     RoomEvent::RoomDelete => {
                         return Err(WSCloseType::RoomClosed);
                     },
@@ -334,11 +758,163 @@ async fn event_loop(
     }
 }
 
+async fn initialize_transports(
+    room: &Arc<Room>,
+    user_id: &str,
+    init_data: InitializationInput,
+    remote: Option<IpAddr>,
+) -> Result<RtcState, WSCloseType> {
+    let router = room
+        .assign_router()
+        .await
+        .map_err(|_| WSCloseType::ServerError)?;
+    let mut rtc_state = RtcState::initialize(
+        room,
+        router.router(),
+        router.webrtc_server(),
+        init_data,
+        remote,
+    )
+    .await
+    .map_err(|_| WSCloseType::ServerError)?;
+
+    let users = room.users();
+    let user = users
+        .get(user_id)
+        .await
+        .ok_or_else(|| WSCloseType::ServerError)?;
+    let mut user = user.write().await;
+    user.set_router(router.router().clone());
+    rtc_state.set_deafened(user.server_deafened()).await;
+
+    Ok(rtc_state)
+}
+
+/// Apply the user's server deafen to the client's consumers, in case the room event was missed
+async fn sync_deafened(room: &Arc<Room>, user_id: &str, rtc_state: &mut RtcState) {
+    let users = room.users();
+    let deafened = match users.get(user_id).await {
+        Some(user) => user.read().await.server_deafened(),
+        None => return,
+    };
+    rtc_state.set_deafened(deafened).await;
+}
+
+/// Consume every producer in the room for a client that enabled auto-subscribe
+async fn auto_consume_all(
+    room: &Arc<Room>,
+    user_id: &str,
+    rtc_state: &mut RtcState,
+    ws_sink: &mut SplitSink<WebSocket, Message>,
+) -> Result<(), WSCloseType> {
+    if rtc_state.auto_subscribe().is_none() {
+        return Ok(());
+    }
+
+    let producers: Vec<(String, ProduceType)> = {
+        let users = room.users();
+        let guard = users.guard().await;
+        let mut producers = Vec::new();
+        for user in guard.iter() {
+            let user = user.read().await;
+            if user.id() != user_id {
+                producers.extend(
+                    user.producers()
+                        .map(|(produce_type, _)| (user.id().to_string(), *produce_type)),
+                );
+            }
+        }
+        producers
+    };
+
+    for (producing_id, produce_type) in producers {
+        auto_consume(
+            room,
+            user_id,
+            &producing_id,
+            produce_type,
+            rtc_state,
+            ws_sink,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Consume a user's producer for a client that enabled auto-subscribe and tell it about the consumer
+async fn auto_consume(
+    room: &Arc<Room>,
+    user_id: &str,
+    producing_id: &str,
+    produce_type: ProduceType,
+    rtc_state: &mut RtcState,
+    ws_sink: &mut SplitSink<WebSocket, Message>,
+) -> Result<(), WSCloseType> {
+    let paused = match rtc_state.auto_subscribe() {
+        Some(auto_subscribe) => auto_subscribe == AutoSubscribe::Paused,
+        None => return Ok(()),
+    };
+    sync_deafened(room, user_id, rtc_state).await;
+
+    let (router, producer) = {
+        let users = room.users();
+        let user = match users.get(producing_id).await {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let user = user.read().await;
+        match (user.router(), user.get_producer(produce_type)) {
+            (Some(router), Some(producer)) => (router.clone(), producer.clone()),
+            _ => return Ok(()),
+        }
+    };
+
+    if rtc_state.consuming(producer.id()) || !rtc_state.can_consume(&router, &producer) {
+        return Ok(());
+    }
+
+    // The consume error is not Send, so it must be gone before anything is sent
+    let consumer = match rtc_state
+        .start_consume(
+            room,
+            &router,
+            producing_id,
+            produce_type,
+            producer.id(),
+            paused,
+        )
+        .await
+    {
+        Ok(consumer) => consumer,
+        Err(err) => {
+            error!(
+                "Failed to auto-subscribe to {:?} producer of user {}: {}",
+                produce_type, producing_id, err
+            );
+            return Ok(());
+        }
+    };
+
+    let event = WSEvent::ConsumerCreated {
+        id: consumer.id(),
+        producer_id: consumer.producer_id(),
+        kind: consumer.kind(),
+        rtp_parameters: consumer.rtp_parameters().clone(),
+        user_id: producing_id.to_string(),
+        produce_type,
+    };
+    ws_sink
+        .send(Message::text(serde_json::to_string(&event)?))
+        .await?;
+    Ok(())
+}
+
 async fn room_info(
     c: WSCommand,
     room: &Arc<Room>,
     ws_sink: &mut SplitSink<WebSocket, Message>,
 ) -> Result<(), WSCloseType> {
+    let recording = room.recording().await;
     let users = room.users();
     let guard = users.guard().await;
     let mut user_info: HashMap<String, UserInfo> = HashMap::new();
@@ -351,7 +927,9 @@ async fn room_info(
         .send(
             WSReplyType::RoomInfo {
                 id: room.id().to_string(),
-                video_allowed: false,
+                video_allowed: room.video_allowed(),
+                audio_profile: room.audio_profile(),
+                recording,
                 users: user_info,
             }
             .to_message(c.id)?,
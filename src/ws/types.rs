@@ -1,12 +1,20 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::num::NonZeroU8;
 use strum::IntoStaticStr;
 use warp::ws::Message;
 
+use mediasoup::consumer::ConsumerLayers;
+use mediasoup::data_consumer::DataConsumerId;
+use mediasoup::data_producer::DataProducerId;
 use mediasoup::prelude::*;
 use mediasoup::rtp_parameters::{MediaKind, RtpCapabilitiesFinalized, RtpParameters};
+use mediasoup::sctp_parameters::SctpStreamParameters;
 
+use crate::rtc::stats::RtcStats;
+use crate::rtc::ConsumerCloseReason;
 use crate::rtc::types::{ConnectTransportData, InitializationInput, TransportInitData};
+use crate::state::room::AudioProfile;
 use crate::state::user::{ProduceType, UserInfo};
 
 #[derive(Deserialize)]
@@ -35,8 +43,13 @@ pub enum WSCommandType {
         #[serde(flatten)]
         connect_data: ConnectTransportData,
     },
+    #[serde(rename_all = "camelCase")]
+    RestartIce {
+        transport_id: TransportId,
+    },
 
     RoomInfo,
+    GetStats,
 
     #[serde(rename_all = "camelCase")]
     StartProduce {
@@ -49,6 +62,17 @@ pub enum WSCommandType {
         #[serde(rename = "type")]
         produce_type: ProduceType,
     },
+    /// Pause a producer in place, e.g. to mute the microphone
+    #[serde(rename_all = "camelCase")]
+    PauseProduce {
+        #[serde(rename = "type")]
+        produce_type: ProduceType,
+    },
+    #[serde(rename_all = "camelCase")]
+    ResumeProduce {
+        #[serde(rename = "type")]
+        produce_type: ProduceType,
+    },
 
     #[serde(rename_all = "camelCase")]
     StartConsume {
@@ -65,6 +89,47 @@ pub enum WSCommandType {
         id: ConsumerId,
         paused: bool,
     },
+    /// Consumers with a higher priority (1 to 255) get bandwidth first
+    SetConsumerPriority {
+        /// Consumer ID
+        id: ConsumerId,
+        priority: NonZeroU8,
+    },
+    RequestKeyFrame {
+        /// Consumer ID
+        id: ConsumerId,
+    },
+    #[serde(rename_all = "camelCase")]
+    SetPreferredLayers {
+        /// Consumer ID
+        id: ConsumerId,
+        spatial_layer: u8,
+        #[serde(default)]
+        temporal_layer: Option<u8>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    StartDataProduce {
+        sctp_stream_parameters: SctpStreamParameters,
+        #[serde(default)]
+        label: String,
+        #[serde(default)]
+        protocol: String,
+    },
+    StopDataProduce {
+        /// Data producer ID
+        id: DataProducerId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    StartDataConsume {
+        user_id: String,
+        data_producer_id: DataProducerId,
+    },
+    StopDataConsume {
+        /// Data consumer ID
+        id: DataConsumerId,
+    },
 }
 
 impl WSReplyType {
@@ -102,19 +167,31 @@ pub enum WSReplyType {
         reply_data: TransportInitData,
     },
     ConnectTransport,
+    #[serde(rename_all = "camelCase")]
+    RestartIce {
+        ice_parameters: IceParameters,
+    },
 
     #[serde(rename_all = "camelCase")]
     RoomInfo {
         id: String,
         video_allowed: bool,
+        audio_profile: AudioProfile,
+        recording: bool,
         users: HashMap<String, UserInfo>,
     },
+    GetStats {
+        #[serde(flatten)]
+        stats: RtcStats,
+    },
 
     #[serde(rename_all = "camelCase")]
     StartProduce {
         producer_id: ProducerId,
     },
     StopProduce,
+    PauseProduce,
+    ResumeProduce,
 
     #[serde(rename_all = "camelCase")]
     StartConsume {
@@ -125,6 +202,25 @@ pub enum WSReplyType {
     },
     StopConsume,
     SetConsumerPause,
+    SetConsumerPriority,
+    RequestKeyFrame,
+    SetPreferredLayers,
+
+    #[serde(rename_all = "camelCase")]
+    StartDataProduce {
+        data_producer_id: DataProducerId,
+    },
+    StopDataProduce,
+
+    #[serde(rename_all = "camelCase")]
+    StartDataConsume {
+        id: DataConsumerId,
+        data_producer_id: DataProducerId,
+        sctp_stream_parameters: Option<SctpStreamParameters>,
+        label: String,
+        protocol: String,
+    },
+    StopDataConsume,
 }
 
 impl WSReplyType {}
@@ -138,6 +234,10 @@ pub enum WSEvent {
     UserLeft {
         id: String,
     },
+    /// Sent to a user who is being kicked, right before the connection is closed
+    Kicked {
+        reason: Option<String>,
+    },
 
     UserStartProduce {
         id: String,
@@ -149,4 +249,82 @@ pub enum WSEvent {
         #[serde(rename = "type")]
         produce_type: ProduceType,
     },
+    UserProducerPaused {
+        id: String,
+        #[serde(rename = "type")]
+        produce_type: ProduceType,
+    },
+    UserProducerResumed {
+        id: String,
+        #[serde(rename = "type")]
+        produce_type: ProduceType,
+    },
+    /// A moderator muted or unmuted a user, also sent to the user themself
+    UserServerMuted {
+        id: String,
+        muted: bool,
+    },
+    /// A moderator deafened or undeafened a user, also sent to the user themself
+    UserServerDeafened {
+        id: String,
+        deafened: bool,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    UserStartDataProduce {
+        id: String,
+        data_producer_id: DataProducerId,
+        label: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    UserStopDataProduce {
+        id: String,
+        data_producer_id: DataProducerId,
+    },
+
+    /// A consumer was created for the client because it enabled auto-subscribe
+    #[serde(rename_all = "camelCase")]
+    ConsumerCreated {
+        id: ConsumerId,
+        producer_id: ProducerId,
+        kind: MediaKind,
+        rtp_parameters: RtpParameters,
+        user_id: String,
+        #[serde(rename = "type")]
+        produce_type: ProduceType,
+    },
+
+    /// A consumer was closed by the server and has been removed
+    ConsumerClosed {
+        id: ConsumerId,
+        reason: ConsumerCloseReason,
+    },
+    /// The layers forwarded by a video consumer changed, no layers means nothing is forwarded
+    ConsumerLayersChanged {
+        id: ConsumerId,
+        layers: Option<ConsumerLayers>,
+    },
+
+    UserSpeaking {
+        id: String,
+        /// Volume in dBvo, from -127 to 0
+        volume: i8,
+    },
+    UserSilent {
+        id: String,
+    },
+    /// No ID means nobody is detected as the main speaker
+    DominantSpeaker {
+        id: Option<String>,
+    },
+
+    RecordingStarted,
+    RecordingStopped,
+
+    /// The room's router was lost and recreated, all transports, producers
+    /// and consumers are gone and the client must send InitializeTransports again
+    #[serde(rename_all = "camelCase")]
+    RouterRecreated {
+        rtp_capabilities: RtpCapabilitiesFinalized,
+    },
 }